@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use colored::*;
+use compiler::disassembler;
+use shared::{config::MachineConfig, executable::Executable};
+use structopt::StructOpt;
+
+/// Turns a compiled binary back into a listing or into source code
+#[derive(StructOpt)]
+struct Args {
+    #[structopt(parse(from_os_str))]
+    input: std::path::PathBuf,
+    /// Where to write the disassembly, stdout by default
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<std::path::PathBuf>,
+    /// Write source code that compiles back into the same binary instead of a listing
+    #[structopt(long = "source")]
+    source: bool,
+    /// Address the program was compiled for, taken from the executable or program header if there is one
+    #[structopt(long = "load-address")]
+    load_address: Option<u16>,
+}
+
+fn main() {
+    let args = Args::from_args();
+    let program = std::fs::read(&args.input).expect("Unable to read input file");
+    let (header, code) = if Executable::detect(&program) {
+        match Executable::from_bytes(&program) {
+            Ok(executable) => (Some(executable.config), executable.code),
+            Err(err) => {
+                println!(
+                    "{}",
+                    format!("ERROR: invalid executable: {}", err).red().bold()
+                );
+                std::process::exit(1);
+            }
+        }
+    } else {
+        match MachineConfig::from_program(&program) {
+            Ok((header, code)) => (header, code.to_vec()),
+            Err(err) => {
+                println!(
+                    "{}",
+                    format!("ERROR: invalid program header: {}", err)
+                        .red()
+                        .bold()
+                );
+                std::process::exit(1);
+            }
+        }
+    };
+    let origin = args
+        .load_address
+        .or(header.as_ref().map(|config| config.load_address))
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    if let Some(config) = header {
+        text += &format!(
+            "# compiled with --registers {} --memory {} --exit-register {} --load-address {} --entry {}\n",
+            config.registers, config.memory_size, config.exit_register, config.load_address, config.entry
+        );
+    }
+    if args.source {
+        match disassembler::to_source(&code, origin) {
+            Ok(source) => text += &source,
+            Err(err) => {
+                println!("{}", format!("ERROR: {}", err).red().bold());
+                std::process::exit(1);
+            }
+        }
+    } else {
+        text += &disassembler::listing(&code, origin);
+    }
+
+    match args.output {
+        Some(path) => std::fs::write(path, text).expect("Unable to write output file"),
+        None => std::io::stdout()
+            .write_all(text.as_bytes())
+            .expect("Unable to write disassembly"),
+    }
+}
@@ -0,0 +1,205 @@
+use std::{collections::BTreeSet, fmt};
+
+use shared::{CAddress, Case, DecodeError, Op, Register, REGISTER_NAMES};
+
+/// Why a binary can't be turned back into source code. Addresses include the origin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisassemblyError {
+    Decode(DecodeError),
+    /// The instruction has no source syntax, like `noop` or a register without a name
+    NoSyntax {
+        pc: usize,
+        op: Op,
+    },
+    /// A jump or call lands outside of the program or inside another instruction, where no label can go
+    BadTarget {
+        pc: usize,
+        target: CAddress,
+    },
+}
+
+impl fmt::Display for DisassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DisassemblyError::Decode(e) => write!(f, "{}", e),
+            DisassemblyError::NoSyntax { pc, op } => {
+                write!(f, "{:?} at {:#04x} can't be written in source code", op, pc)
+            }
+            DisassemblyError::BadTarget { pc, target } => write!(
+                f,
+                "instruction at {:#04x} jumps to {:#04x}, which isn't the start of an instruction",
+                pc, target
+            ),
+        }
+    }
+}
+
+/// A decoded instruction of a binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Address of the instruction, origin included
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    /// An error for a byte that doesn't start a valid instruction
+    pub op: Result<Op, DecodeError>,
+}
+
+/// Decodes `code` loaded at `origin` from start to end. Bytes that don't decode get a line of their own
+pub fn disassemble(code: &[u8], origin: CAddress) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let addr = origin as usize + offset;
+        let (op, size) = match shared::decode(code, offset) {
+            Ok((op, size)) => (Ok(op), size),
+            Err(DecodeError::UnknownOpcode { opcode, .. }) => {
+                (Err(DecodeError::UnknownOpcode { pc: addr, opcode }), 1)
+            }
+            Err(DecodeError::UnknownCondition { code, .. }) => {
+                (Err(DecodeError::UnknownCondition { pc: addr, code }), 1)
+            }
+            Err(DecodeError::Truncated { .. }) => (Err(DecodeError::Truncated { pc: addr }), 1),
+        };
+        lines.push(Line {
+            addr,
+            bytes: code[offset..offset + size].to_vec(),
+            op,
+        });
+        offset += size;
+    }
+    lines
+}
+
+/// Name of the label generated for `addr`
+pub fn label(addr: CAddress) -> String {
+    format!("L_{:#04x}", addr)
+}
+
+fn register(reg: Register) -> Option<&'static str> {
+    REGISTER_NAMES.get(reg as usize).copied()
+}
+
+/// `op` in source syntax, with jump targets written as generated labels
+fn source(op: Op) -> Option<String> {
+    let r = register;
+    Some(match op {
+        Op::NOOP => return None,
+        Op::HALT | Op::BRK | Op::RET | Op::EI | Op::DI | Op::RETI => op.row().mnemonic.to_owned(),
+
+        Op::MOVRN(d, n) => format!("mov {} to {}", n, r(d)?),
+        Op::MOVRR(d, s) => format!("mov {} to {}", r(s)?, r(d)?),
+        Op::MOVRA(d, a) => format!("mov ${} to {}", a, r(d)?),
+        Op::MOVRX(d, s) => format!("mov ${} to {}", r(s)?, r(d)?),
+        Op::MOVAN(a, n) => format!("mov {} to ${}", n, a),
+        Op::MOVAR(a, s) => format!("mov {} to ${}", r(s)?, a),
+        Op::MOVAA(a, b) => format!("mov ${} to ${}", b, a),
+        Op::MOVAX(a, s) => format!("mov ${} to ${}", r(s)?, a),
+        Op::MOVXN(d, n) => format!("mov {} to ${}", n, r(d)?),
+        Op::MOVXR(d, s) => format!("mov {} to ${}", r(s)?, r(d)?),
+        Op::MOVXA(d, a) => format!("mov ${} to ${}", a, r(d)?),
+        Op::MOVXX(d, s) => format!("mov ${} to ${}", r(s)?, r(d)?),
+        Op::MOVRP(d, hi, lo) => format!("mov ${}:{} to {}", r(hi)?, r(lo)?, r(d)?),
+        Op::MOVPN(hi, lo, n) => format!("mov {} to ${}:{}", n, r(hi)?, r(lo)?),
+        Op::MOVPR(hi, lo, s) => format!("mov {} to ${}:{}", r(s)?, r(hi)?, r(lo)?),
+
+        Op::ADDRN(d, n) | Op::ADCRN(d, n) => format!("{} {} to {}", op.row().mnemonic, n, r(d)?),
+        Op::ADDRR(d, s) | Op::ADCRR(d, s) => {
+            format!("{} {} to {}", op.row().mnemonic, r(s)?, r(d)?)
+        }
+        Op::SUBRN(d, n) | Op::SBBRN(d, n) => format!("{} {} from {}", op.row().mnemonic, n, r(d)?),
+        Op::SUBRR(d, s) | Op::SBBRR(d, s) => {
+            format!("{} {} from {}", op.row().mnemonic, r(s)?, r(d)?)
+        }
+        Op::MULRN(d, n) | Op::ANDRN(d, n) | Op::XORRN(d, n) | Op::ORRN(d, n) => {
+            format!("{} {} with {}", op.row().mnemonic, r(d)?, n)
+        }
+        Op::MULRR(d, s) | Op::ANDRR(d, s) | Op::XORRR(d, s) | Op::ORRR(d, s) => {
+            format!("{} {} with {}", op.row().mnemonic, r(d)?, r(s)?)
+        }
+        Op::DIVRN(d, n) | Op::MODRN(d, n) => format!("{} {} by {}", op.row().mnemonic, r(d)?, n),
+        Op::DIVRR(d, s) | Op::MODRR(d, s) => {
+            format!("{} {} by {}", op.row().mnemonic, r(d)?, r(s)?)
+        }
+
+        Op::SHR(x) | Op::SHL(x) | Op::PRINT(x) | Op::READ(x) | Op::PUSH(x) | Op::POP(x) => {
+            format!("{} {}", op.row().mnemonic, r(x)?)
+        }
+
+        Op::JMP(to) => format!("jmp to {}", label(to)),
+        Op::CALL(to) => format!("call {}", label(to)),
+        Op::JMPIF(case, to) => {
+            let cond = match case {
+                Case::EQ(a, b)
+                | Case::NEQ(a, b)
+                | Case::LSR(a, b)
+                | Case::GRT(a, b)
+                | Case::LSREQ(a, b)
+                | Case::GRTEQ(a, b) => format!("{} {} {}", r(a)?, case.row().mnemonic, r(b)?),
+                _ => case.row().mnemonic.to_owned(),
+            };
+            format!("jmp if {} to {}", cond, label(to))
+        }
+    })
+}
+
+fn target(op: Op) -> Option<CAddress> {
+    match op {
+        Op::JMP(to) | Op::JMPIF(_, to) | Op::CALL(to) => Some(to),
+        _ => None,
+    }
+}
+
+/// Jump and call targets of `lines`
+fn targets(lines: &[Line]) -> BTreeSet<CAddress> {
+    lines
+        .iter()
+        .filter_map(|line| line.op.ok().and_then(target))
+        .collect()
+}
+
+/// Addresses, raw bytes and source of every instruction, with the generated labels in between
+pub fn listing(code: &[u8], origin: CAddress) -> String {
+    let lines = disassemble(code, origin);
+    let targets = targets(&lines);
+    let mut out = String::new();
+    for line in lines.iter() {
+        if targets.contains(&(line.addr as CAddress)) {
+            out += &format!("{}:\n", label(line.addr as CAddress));
+        }
+        let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let text = match line.op {
+            Ok(op) => source(op).unwrap_or_else(|| format!("{:?}", op)),
+            Err(_) => "??".to_owned(),
+        };
+        out += &format!("{:#06x}  {:<18} {}\n", line.addr, bytes.join(" "), text);
+    }
+    out
+}
+
+/// Source code that assembles back into `code` when compiled for `origin`
+pub fn to_source(code: &[u8], origin: CAddress) -> Result<String, DisassemblyError> {
+    let lines = disassemble(code, origin);
+    let end = origin as usize + code.len();
+    let targets = targets(&lines);
+    let mut out = String::new();
+    for line in lines.iter() {
+        if targets.contains(&(line.addr as CAddress)) {
+            out += &format!("label as {}\n", label(line.addr as CAddress));
+        }
+        let op = line.op.map_err(DisassemblyError::Decode)?;
+        if let Some(to) = target(op) {
+            if to as usize != end && !lines.iter().any(|line| line.addr == to as usize) {
+                return Err(DisassemblyError::BadTarget {
+                    pc: line.addr,
+                    target: to,
+                });
+            }
+        }
+        let text = source(op).ok_or(DisassemblyError::NoSyntax { pc: line.addr, op })?;
+        out += &format!("    {}\n", text);
+    }
+    if targets.contains(&(end as CAddress)) {
+        out += &format!("label as {}\n", label(end as CAddress));
+    }
+    Ok(out)
+}
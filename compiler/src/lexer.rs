@@ -7,16 +7,19 @@ pub enum TokenKind {
     From,
     As,
     With,
+    By,
     If,
 
     Number(i32),
     Ins(Instruction),
     Reg(Register),
+    Flag(Flag),
 
     Lesser,
     Greater,
     Equal,
     Exclamation,
+    Colon,
 
     Symbol(String),
 }
@@ -25,16 +28,30 @@ pub enum TokenKind {
 pub enum Instruction {
     Mov,
     Add,
+    Adc,
     Sub,
+    Sbb,
+    Mul,
+    Div,
+    Mod,
     Shl,
     Shr,
     Jmp,
+    Call,
+    Ret,
+    Push,
+    Pop,
     Print,
+    Read,
     And,
     Xor,
     Or,
     Label,
     Halt,
+    Brk,
+    Ei,
+    Di,
+    Reti,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -49,6 +66,14 @@ pub enum Register {
     N,
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Flag {
+    Zero,
+    Carry,
+    Overflow,
+    Negative,
+}
+
 impl FromStr for Instruction {
     type Err = ();
 
@@ -56,16 +81,30 @@ impl FromStr for Instruction {
         match s.to_lowercase().as_str() {
             "mov" => Ok(Self::Mov),
             "add" => Ok(Self::Add),
+            "adc" => Ok(Self::Adc),
             "sub" => Ok(Self::Sub),
+            "sbb" => Ok(Self::Sbb),
+            "mul" => Ok(Self::Mul),
+            "div" => Ok(Self::Div),
+            "mod" => Ok(Self::Mod),
             "shl" => Ok(Self::Shl),
             "shr" => Ok(Self::Shr),
             "jmp" => Ok(Self::Jmp),
+            "call" => Ok(Self::Call),
+            "ret" => Ok(Self::Ret),
+            "push" => Ok(Self::Push),
+            "pop" => Ok(Self::Pop),
             "print" => Ok(Self::Print),
+            "read" => Ok(Self::Read),
             "and" => Ok(Self::And),
             "xor" => Ok(Self::Xor),
             "or" => Ok(Self::Or),
             "label" => Ok(Self::Label),
             "halt" => Ok(Self::Halt),
+            "brk" => Ok(Self::Brk),
+            "ei" => Ok(Self::Ei),
+            "di" => Ok(Self::Di),
+            "reti" => Ok(Self::Reti),
 
             _ => Err(()),
         }
@@ -91,6 +130,21 @@ impl FromStr for Register {
     }
 }
 
+impl FromStr for Flag {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "zero" => Ok(Self::Zero),
+            "carry" => Ok(Self::Carry),
+            "overflow" => Ok(Self::Overflow),
+            "negative" => Ok(Self::Negative),
+
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
@@ -190,16 +244,20 @@ impl Lexer<'_> {
                     "as" => TokenKind::As,
                     "from" => TokenKind::From,
                     "with" => TokenKind::With,
+                    "by" => TokenKind::By,
                     "if" => TokenKind::If,
                     "<" => TokenKind::Lesser,
                     ">" => TokenKind::Greater,
                     "=" => TokenKind::Equal,
                     "!" => TokenKind::Exclamation,
+                    ":" => TokenKind::Colon,
                     _ => {
                         if let Ok(ins) = word.parse::<Instruction>() {
                             TokenKind::Ins(ins)
                         } else if let Ok(reg) = word.parse::<Register>() {
                             TokenKind::Reg(reg)
+                        } else if let Ok(flag) = word.parse::<Flag>() {
+                            TokenKind::Flag(flag)
                         } else if let Ok(num) = word.parse::<i32>() {
                             TokenKind::Number(num)
                         } else {
@@ -1,12 +1,19 @@
+pub mod disassembler;
 pub mod lexer;
 
 use std::collections::HashMap;
 
-use lexer::{Instruction, Register, Token, TokenKind};
-use shared::{Case, Op};
+use lexer::{Flag, Instruction, Register, Token, TokenKind};
+use shared::{config::MachineConfig, debuginfo::Location, CAddress, Case, Op};
 
 pub struct Parser {
     pub input: Vec<lexer::Token>,
+    /// Address of every label, filled in by `parse`
+    pub labels: HashMap<String, CAddress>,
+    /// Machine the program is compiled for. Labels start at its load address
+    pub config: MachineConfig,
+    /// Address and source location of every instruction, filled in by `parse`
+    pub locations: Vec<(CAddress, Location)>,
 }
 
 #[derive(Debug)]
@@ -15,9 +22,9 @@ pub struct ParserError<'a> {
     pub responsible: &'a lexer::Token,
 }
 
-impl Into<u8> for Register {
-    fn into(self) -> u8 {
-        match self {
+impl From<Register> for u8 {
+    fn from(reg: Register) -> u8 {
+        match reg {
             Register::N => 0,
             Register::X => 1,
             Register::Y => 2,
@@ -41,19 +48,102 @@ impl TryInto<u8> for TokenKind {
     }
 }
 
+fn numeral(token: &Token) -> Result<u8, ParserError<'_>> {
+    match token.kind {
+        TokenKind::Number(n) => n.try_into().map_err(|_| ParserError {
+            cause: "Integers should be between 0 and 255 (included)",
+            responsible: token,
+        }),
+        _ => Err(ParserError {
+            cause: "Expected a number here",
+            responsible: token,
+        }),
+    }
+}
+
+fn address(token: &Token) -> Result<CAddress, ParserError<'_>> {
+    match token.kind {
+        TokenKind::Number(n) => n.try_into().map_err(|_| ParserError {
+            cause: "Addresses should be between 0 and 65535 (included)",
+            responsible: token,
+        }),
+        _ => Err(ParserError {
+            cause: "Expected an address here",
+            responsible: token,
+        }),
+    }
+}
+
+/// The low register of a `$hi:lo` register pair, if a colon follows the high register
+fn pair_low<'a>(iter: &mut std::slice::Iter<'a, Token>) -> Result<Option<u8>, ParserError<'a>> {
+    match iter.as_slice().first() {
+        Some(colon) if colon.kind == TokenKind::Colon => {
+            iter.next();
+            let lo = iter.next().ok_or(ParserError {
+                cause: "Missing the low register of the pair after here",
+                responsible: colon,
+            })?;
+            let lo = lo.kind.clone().try_into().map_err(|_| ParserError {
+                cause: "Expected a register here",
+                responsible: lo,
+            })?;
+            Ok(Some(lo))
+        }
+        _ => Ok(None),
+    }
+}
+
 impl Parser {
-    pub fn parse(&mut self) -> Result<Vec<shared::Op>, ParserError> {
+    pub fn new(input: Vec<lexer::Token>) -> Self {
+        Self::with_config(input, MachineConfig::default())
+    }
+
+    /// A parser compiling for `config` instead of the default machine
+    pub fn with_config(input: Vec<lexer::Token>, config: MachineConfig) -> Self {
+        Self {
+            input,
+            labels: HashMap::new(),
+            config,
+            locations: Vec::new(),
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<shared::Op>, ParserError<'_>> {
         let mut iter = self.input.iter();
         let mut code = Vec::<Op>::new();
-        let mut labels: HashMap<String, u8> = HashMap::new();
+        let labels = &mut self.labels;
+        labels.clear();
+        self.locations.clear();
         let mut rpoints: Vec<(String, usize, &Token)> = Vec::new();
+        // the instruction token every op was parsed from
+        let mut origins: Vec<&Token> = Vec::new();
         while let Some(i) = iter.next() {
             match i.kind {
                 TokenKind::Ins(ins) => match ins {
                     Instruction::Halt => {
                         code.push(Op::HALT);
                     }
-                    Instruction::Print | Instruction::Shl | Instruction::Shr => {
+                    Instruction::Ret => {
+                        code.push(Op::RET);
+                    }
+                    Instruction::Brk => {
+                        code.push(Op::BRK);
+                    }
+                    Instruction::Ei => {
+                        code.push(Op::EI);
+                    }
+                    Instruction::Di => {
+                        code.push(Op::DI);
+                    }
+                    Instruction::Reti => {
+                        code.push(Op::RETI);
+                    }
+                    Instruction::Print
+                    | Instruction::Read
+                    | Instruction::Shl
+                    | Instruction::Shr
+                    | Instruction::Push
+                    | Instruction::Pop => {
                         let x = iter.next().ok_or(ParserError {
                             cause: "Missing a register after here",
                             responsible: i,
@@ -65,8 +155,11 @@ impl Parser {
 
                         let op = match ins {
                             Instruction::Print => Op::PRINT(x),
+                            Instruction::Read => Op::READ(x),
                             Instruction::Shl => Op::SHL(x),
                             Instruction::Shr => Op::SHR(x),
+                            Instruction::Push => Op::PUSH(x),
+                            Instruction::Pop => Op::POP(x),
                             _ => unreachable!(),
                         };
                         code.push(op);
@@ -78,62 +171,99 @@ impl Parser {
                         })?;
                         let mut case = None;
                         if w.kind == TokenKind::If {
-                            let x = iter.next().ok_or(ParserError {
-                                cause: "Missing left-hand side of comparison after here",
+                            let mut x = iter.next().ok_or(ParserError {
+                                cause: "Missing flag or left-hand side of comparison after here",
                                 responsible: w,
                             })?;
-                            let c = iter.next().ok_or(ParserError {
-                                cause: "Missing comparison operator after here",
-                                responsible: x,
-                            })?;
+                            let negated = x.kind == TokenKind::Exclamation;
+                            if negated {
+                                x = iter.next().ok_or(ParserError {
+                                    cause: "Missing a flag after here",
+                                    responsible: x,
+                                })?;
+                            }
+                            if let TokenKind::Flag(flag) = x.kind {
+                                case = Some(match (flag, negated) {
+                                    (Flag::Zero, false) => Case::ZERO,
+                                    (Flag::Zero, true) => Case::NZERO,
+                                    (Flag::Carry, false) => Case::CARRY,
+                                    (Flag::Carry, true) => Case::NCARRY,
+                                    (Flag::Overflow, false) => Case::OVFL,
+                                    (Flag::Overflow, true) => Case::NOVFL,
+                                    (Flag::Negative, false) => Case::NEG,
+                                    (Flag::Negative, true) => Case::NNEG,
+                                });
+                                w = iter.next().ok_or(ParserError {
+                                    cause: "Missing 'to' after here",
+                                    responsible: x,
+                                })?;
+                            } else if negated {
+                                Err(ParserError {
+                                    cause: "Expected a flag here",
+                                    responsible: x,
+                                })?
+                            } else {
+                                let c = iter.next().ok_or(ParserError {
+                                    cause: "Missing comparison operator after here",
+                                    responsible: x,
+                                })?;
 
-                            let mut y = iter.next().ok_or(ParserError {
-                                cause: "Missing right-hand side of comparison after here",
-                                responsible: c,
-                            })?;
-                            let mut c2 = None;
-                            match y.kind.clone() {
-                                TokenKind::Reg(_) => {}
-                                tk
-                                @ (TokenKind::Equal | TokenKind::Greater | TokenKind::Lesser) => {
-                                    c2 = Some(tk);
-                                    y = iter.next().ok_or(ParserError {
-                                        cause: "Missing right-hand side of comparison after here",
+                                let mut y = iter.next().ok_or(ParserError {
+                                    cause: "Missing right-hand side of comparison after here",
+                                    responsible: c,
+                                })?;
+                                let mut c2 = None;
+                                match y.kind.clone() {
+                                    TokenKind::Reg(_) => {}
+                                    tk @ (TokenKind::Equal
+                                    | TokenKind::Greater
+                                    | TokenKind::Lesser) => {
+                                        c2 = Some(tk);
+                                        y = iter.next().ok_or(ParserError {
+                                            cause:
+                                                "Missing right-hand side of comparison after here",
+                                            responsible: y,
+                                        })?;
+                                    }
+                                    _ => Err(ParserError {
+                                        cause: "Must be a register",
                                         responsible: y,
-                                    })?;
+                                    })?,
                                 }
-                                _ => Err(ParserError {
-                                    cause: "Must be a register",
-                                    responsible: y,
-                                })?,
-                            }
 
-                            w = iter.next().ok_or(ParserError {
-                                cause: "Missing 'to' after here",
-                                responsible: y,
-                            })?;
+                                w = iter.next().ok_or(ParserError {
+                                    cause: "Missing 'to' after here",
+                                    responsible: y,
+                                })?;
 
-                            let x = x.kind.clone().try_into().map_err(|_| ParserError {
-                                cause: "Must be a register",
-                                responsible: x,
-                            })?;
-                            let y = y.kind.clone().try_into().map_err(|_| ParserError {
-                                cause: "Must be a register",
-                                responsible: y,
-                            })?;
+                                let x = x.kind.clone().try_into().map_err(|_| ParserError {
+                                    cause: "Must be a register",
+                                    responsible: x,
+                                })?;
+                                let y = y.kind.clone().try_into().map_err(|_| ParserError {
+                                    cause: "Must be a register",
+                                    responsible: y,
+                                })?;
 
-                            case = Some(match (c.kind.clone(), c2) {
-                                (TokenKind::Greater, None) => Case::GRT(x, y),
-                                (TokenKind::Greater, Some(TokenKind::Equal)) => Case::GRTEQ(x, y),
-                                (TokenKind::Lesser, None) => Case::LSR(x, y),
-                                (TokenKind::Lesser, Some(TokenKind::Equal)) => Case::LSREQ(x, y),
-                                (TokenKind::Equal, Some(TokenKind::Equal)) => Case::EQ(x, y),
-                                (TokenKind::Exclamation, Some(TokenKind::Equal)) => Case::NEQ(x, y),
-                                _ => Err(ParserError {
-                                    cause: "Expected a comparison operator here",
-                                    responsible: c,
-                                })?,
-                            });
+                                case = Some(match (c.kind.clone(), c2) {
+                                    (TokenKind::Greater, None) => Case::GRT(x, y),
+                                    (TokenKind::Greater, Some(TokenKind::Equal)) => {
+                                        Case::GRTEQ(x, y)
+                                    }
+                                    (TokenKind::Lesser, None) => Case::LSR(x, y),
+                                    (TokenKind::Lesser, Some(TokenKind::Equal)) => {
+                                        Case::LSREQ(x, y)
+                                    }
+                                    (TokenKind::Equal, Some(TokenKind::Equal)) => Case::EQ(x, y),
+                                    (TokenKind::Exclamation, Some(TokenKind::Equal)) => {
+                                        Case::NEQ(x, y)
+                                    }
+                                    _ => Err(ParserError {
+                                        cause: "Expected a comparison operator here",
+                                        responsible: c,
+                                    })?,
+                                });
+                            }
                         }
 
                         if w.kind != TokenKind::To {
@@ -163,6 +293,22 @@ impl Parser {
                             })?,
                         }
                     }
+                    Instruction::Call => {
+                        let to = iter.next().ok_or(ParserError {
+                            cause: "Missing label after here",
+                            responsible: i,
+                        })?;
+                        match to.kind {
+                            TokenKind::Symbol(ref label) => {
+                                rpoints.push((label.to_owned(), code.len(), to));
+                                code.push(Op::CALL(0xEA));
+                            }
+                            _ => Err(ParserError {
+                                cause: "Is not a label",
+                                responsible: to,
+                            })?,
+                        }
+                    }
                     Instruction::Label => {
                         let w = iter.next().ok_or(ParserError {
                             cause: "Missing 'as' after here",
@@ -179,9 +325,14 @@ impl Parser {
                             responsible: w,
                         })?;
                         if let TokenKind::Symbol(s) = name.kind.clone() {
-                            let addr: usize = code.iter().map(|op| op.get_size()).sum();
+                            let addr: usize = self.config.load_address as usize
+                                + code.iter().map(|op| op.get_size()).sum::<usize>();
 
-                            if labels.insert(s, addr.try_into().unwrap()).is_some() {
+                            let addr = addr.try_into().map_err(|_| ParserError {
+                                cause: "Label lies outside of the 16-bit address space",
+                                responsible: name,
+                            })?;
+                            if labels.insert(s, addr).is_some() {
                                 Err(ParserError {
                                     cause: "Label already defined previously",
                                     responsible: name,
@@ -194,13 +345,31 @@ impl Parser {
                             })?;
                         }
                     }
-                    Instruction::Or | Instruction::Xor | Instruction::And => {
+                    Instruction::Or
+                    | Instruction::Xor
+                    | Instruction::And
+                    | Instruction::Mul
+                    | Instruction::Div
+                    | Instruction::Mod => {
+                        let (conn, missing_conn, expected_conn) = match ins {
+                            Instruction::Div | Instruction::Mod => (
+                                TokenKind::By,
+                                "Missing 'by' after here",
+                                "Expected 'by' here",
+                            ),
+                            _ => (
+                                TokenKind::With,
+                                "Missing 'with' after here",
+                                "Expected 'with' here",
+                            ),
+                        };
+
                         let a = iter.next().ok_or(ParserError {
                             cause: "Missing register after here",
                             responsible: i,
                         })?;
                         let w = iter.next().ok_or(ParserError {
-                            cause: "Missing 'with' after here",
+                            cause: missing_conn,
                             responsible: a,
                         })?;
                         let b = iter.next().ok_or(ParserError {
@@ -213,9 +382,9 @@ impl Parser {
                             responsible: a,
                         })?;
 
-                        if w.kind != TokenKind::With {
+                        if w.kind != conn {
                             Err(ParserError {
-                                cause: "Expected 'with' here",
+                                cause: expected_conn,
                                 responsible: w,
                             })?
                         }
@@ -230,6 +399,9 @@ impl Parser {
                                     Instruction::Xor => Op::XORRN(x, y),
                                     Instruction::Or => Op::ORRN(x, y),
                                     Instruction::And => Op::ANDRN(x, y),
+                                    Instruction::Mul => Op::MULRN(x, y),
+                                    Instruction::Div => Op::DIVRN(x, y),
+                                    Instruction::Mod => Op::MODRN(x, y),
                                     _ => unreachable!(),
                                 }
                             }
@@ -237,6 +409,9 @@ impl Parser {
                                 Instruction::Xor => Op::XORRR(x, r.into()),
                                 Instruction::Or => Op::ORRR(x, r.into()),
                                 Instruction::And => Op::ANDRR(x, r.into()),
+                                Instruction::Mul => Op::MULRR(x, r.into()),
+                                Instruction::Div => Op::DIVRR(x, r.into()),
+                                Instruction::Mod => Op::MODRR(x, r.into()),
                                 _ => unreachable!(),
                             },
                             _ => Err(ParserError {
@@ -247,7 +422,7 @@ impl Parser {
                         code.push(op);
                     }
 
-                    Instruction::Add => {
+                    Instruction::Add | Instruction::Adc => {
                         let a = iter.next().ok_or(ParserError {
                             cause: "Missing register or number after here",
                             responsible: i,
@@ -279,9 +454,15 @@ impl Parser {
                                     cause: "Integers should be between 0 and 255 (included)",
                                     responsible: a,
                                 })?;
-                                Op::ADDRN(y, x)
+                                match ins {
+                                    Instruction::Add => Op::ADDRN(y, x),
+                                    _ => Op::ADCRN(y, x),
+                                }
                             }
-                            TokenKind::Reg(r) => Op::ADDRR(y, r.into()),
+                            TokenKind::Reg(r) => match ins {
+                                Instruction::Add => Op::ADDRR(y, r.into()),
+                                _ => Op::ADCRR(y, r.into()),
+                            },
                             _ => Err(ParserError {
                                 cause: "Expected a register or a number here",
                                 responsible: a,
@@ -290,7 +471,7 @@ impl Parser {
                         code.push(op);
                     }
 
-                    Instruction::Sub => {
+                    Instruction::Sub | Instruction::Sbb => {
                         let a = iter.next().ok_or(ParserError {
                             cause: "Missing register or number after here",
                             responsible: i,
@@ -322,9 +503,15 @@ impl Parser {
                                     cause: "Integers should be between 0 and 255 (included)",
                                     responsible: a,
                                 })?;
-                                Op::SUBRN(y, x)
+                                match ins {
+                                    Instruction::Sub => Op::SUBRN(y, x),
+                                    _ => Op::SBBRN(y, x),
+                                }
                             }
-                            TokenKind::Reg(r) => Op::SUBRR(y, r.into()),
+                            TokenKind::Reg(r) => match ins {
+                                Instruction::Sub => Op::SUBRR(y, r.into()),
+                                _ => Op::SBBRR(y, r.into()),
+                            },
                             _ => Err(ParserError {
                                 cause: "Expected a register or a number here",
                                 responsible: a,
@@ -339,12 +526,14 @@ impl Parser {
                             responsible: i,
                         })?;
                         let mut deref_a = false;
+                        let mut pair_a = None;
                         if a.kind == TokenKind::Deref {
                             deref_a = true;
                             a = iter.next().ok_or(ParserError {
                                 cause: "Nothing to dereference after here",
                                 responsible: a,
-                            })?
+                            })?;
+                            pair_a = pair_low(&mut iter)?;
                         }
 
                         let w = iter.next().ok_or(ParserError {
@@ -363,96 +552,100 @@ impl Parser {
                             responsible: w,
                         })?;
                         let mut deref_b = false;
+                        let mut pair_b = None;
                         if b.kind == TokenKind::Deref {
                             deref_b = true;
                             b = iter.next().ok_or(ParserError {
                                 cause: "Nothing to dereference after here",
                                 responsible: b,
-                            })?
+                            })?;
+                            pair_b = pair_low(&mut iter)?;
                         }
 
-                        let op = match a.kind {
-                            TokenKind::Number(n) => {
-                                let n: u8 = n.try_into().map_err(|_| ParserError {
-                                    cause: "Integers should be between 0 and 255 (included)",
-                                    responsible: a,
-                                })?;
-                                match b.kind {
+                        // a register pair is named by its high register, a or b
+                        let op = match (pair_a, pair_b, &a.kind, &b.kind) {
+                            (Some(lo), None, TokenKind::Reg(hi), TokenKind::Reg(d)) if !deref_b => {
+                                Op::MOVRP((*d).into(), (*hi).into(), lo)
+                            }
+                            (None, Some(lo), TokenKind::Number(_), TokenKind::Reg(hi))
+                                if !deref_a =>
+                            {
+                                Op::MOVPN((*hi).into(), lo, numeral(a)?)
+                            }
+                            (None, Some(lo), TokenKind::Reg(s), TokenKind::Reg(hi)) if !deref_a => {
+                                Op::MOVPR((*hi).into(), lo, (*s).into())
+                            }
+                            (Some(_), _, _, _) | (_, Some(_), _, _) => Err(ParserError {
+                                cause: "Register pairs can't be moved to or from memory",
+                                responsible: i,
+                            })?,
+                            _ => match a.kind {
+                                TokenKind::Number(_) => match b.kind {
                                     TokenKind::Reg(d) => {
                                         let d: u8 = d.into();
                                         if deref_b {
                                             if deref_a {
-                                                Op::MOVXA(d, n)
+                                                Op::MOVXA(d, address(a)?)
                                             } else {
-                                                Op::MOVXN(d, n)
+                                                Op::MOVXN(d, numeral(a)?)
                                             }
                                         } else {
                                             if deref_a {
-                                                Op::MOVRA(d, n)
+                                                Op::MOVRA(d, address(a)?)
                                             } else {
-                                                Op::MOVRN(d, n)
+                                                Op::MOVRN(d, numeral(a)?)
                                             }
                                         }
                                     }
-                                    TokenKind::Number(d) if deref_b => {
-                                        let d: u8 = d.try_into().map_err(|_| ParserError {
-                                            cause:
-                                                "Integers should be between 0 and 255 (included)",
-                                            responsible: b,
-                                        })?;
+                                    TokenKind::Number(_) if deref_b => {
                                         if deref_a {
-                                            Op::MOVAA(d, n)
+                                            Op::MOVAA(address(b)?, address(a)?)
                                         } else {
-                                            Op::MOVAN(d, n)
+                                            Op::MOVAN(address(b)?, numeral(a)?)
                                         }
                                     }
                                     _ => Err(ParserError {
                                         cause: "Expected a register or an address here",
                                         responsible: b,
                                     })?,
-                                }
-                            }
-                            TokenKind::Reg(s) => {
-                                let s: u8 = s.into();
-                                match b.kind {
-                                    TokenKind::Reg(d) => {
-                                        let d: u8 = d.into();
-                                        if deref_b {
-                                            if deref_a {
-                                                Op::MOVXX(d, s)
+                                },
+                                TokenKind::Reg(s) => {
+                                    let s: u8 = s.into();
+                                    match b.kind {
+                                        TokenKind::Reg(d) => {
+                                            let d: u8 = d.into();
+                                            if deref_b {
+                                                if deref_a {
+                                                    Op::MOVXX(d, s)
+                                                } else {
+                                                    Op::MOVXR(d, s)
+                                                }
                                             } else {
-                                                Op::MOVXR(d, s)
+                                                if deref_a {
+                                                    Op::MOVRX(d, s)
+                                                } else {
+                                                    Op::MOVRR(d, s)
+                                                }
                                             }
-                                        } else {
+                                        }
+                                        TokenKind::Number(_) if deref_b => {
                                             if deref_a {
-                                                Op::MOVRX(d, s)
+                                                Op::MOVAX(address(b)?, s)
                                             } else {
-                                                Op::MOVRR(d, s)
+                                                Op::MOVAR(address(b)?, s)
                                             }
                                         }
-                                    }
-                                    TokenKind::Number(d) if deref_b => {
-                                        let d: u8 = d.try_into().map_err(|_| ParserError {
-                                            cause:
-                                                "Integers should be between 0 and 255 (included)",
+                                        _ => Err(ParserError {
+                                            cause: "Expected a register or an address here",
                                             responsible: b,
-                                        })?;
-                                        if deref_a {
-                                            Op::MOVAX(d, s)
-                                        } else {
-                                            Op::MOVAR(d, s)
-                                        }
+                                        })?,
                                     }
-                                    _ => Err(ParserError {
-                                        cause: "Expected a register or an address here",
-                                        responsible: b,
-                                    })?,
                                 }
-                            }
-                            _ => Err(ParserError {
-                                cause: "Expected a register, an address or a number here",
-                                responsible: a,
-                            })?,
+                                _ => Err(ParserError {
+                                    cause: "Expected a register, an address or a number here",
+                                    responsible: a,
+                                })?,
+                            },
                         };
                         code.push(op);
                     }
@@ -460,10 +653,11 @@ impl Parser {
                 _ => {
                     return Err(ParserError {
                         cause: "Expected an operation or directive here",
-                        responsible: &i,
+                        responsible: i,
                     })
                 }
             }
+            origins.resize(code.len(), i);
         }
 
         for (name, off, token) in rpoints.into_iter() {
@@ -474,71 +668,40 @@ impl Parser {
             match code.get_mut(off).unwrap() {
                 Op::JMP(to) => *to = addr,
                 Op::JMPIF(_, to) => *to = addr,
+                Op::CALL(to) => *to = addr,
                 _ => unreachable!(),
             }
         }
 
+        let mut end = self.config.load_address as usize;
+        for (op, token) in code.iter().zip(origins) {
+            self.config.check(op).map_err(|cause| ParserError {
+                cause,
+                responsible: token,
+            })?;
+            self.locations.push((
+                end as CAddress,
+                Location {
+                    line: token.line + 1,
+                    column: token.range.start + 1,
+                },
+            ));
+            end += op.get_size();
+            if end > self.config.memory_size {
+                return Err(ParserError {
+                    cause: "Instruction lies outside of this machine profile's memory",
+                    responsible: token,
+                });
+            }
+        }
+
         Ok(code)
     }
 }
 
 pub fn to_bytes(ops: Vec<Op>, dest: &mut Vec<u8>) {
     for op in ops.into_iter() {
-        dest.push(op.get_opcode());
-        match op {
-            Op::MOVAA(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVAR(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVAN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVAX(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVXA(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVXR(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVXN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVXX(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVRA(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVRR(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MOVRX(a, b) => dest.extend_from_slice(&[a, b]),
-
-            Op::ADDRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::ADDRR(a, b) => dest.extend_from_slice(&[a, b]),
-
-            Op::SUBRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::SUBRR(a, b) => dest.extend_from_slice(&[a, b]),
-
-            Op::MULRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::MULRR(a, b) => dest.extend_from_slice(&[a, b]),
-
-            Op::DIVRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::DIVRR(a, b) => dest.extend_from_slice(&[a, b]),
-
-            Op::ORRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::ORRR(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::ANDRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::ANDRR(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::XORRN(a, b) => dest.extend_from_slice(&[a, b]),
-            Op::XORRR(a, b) => dest.extend_from_slice(&[a, b]),
-
-            Op::SHR(x) => dest.push(x),
-            Op::SHL(x) => dest.push(x),
-            Op::PRINT(x) => dest.push(x),
-
-            Op::JMP(to) => dest.push(to),
-            Op::JMPIF(case, to) => {
-                dest.push(case.get_opcode());
-                match case {
-                    Case::EQ(x, y) => dest.extend_from_slice(&[x, y]),
-                    Case::NEQ(x, y) => dest.extend_from_slice(&[x, y]),
-                    Case::GRT(x, y) => dest.extend_from_slice(&[x, y]),
-                    Case::LSR(x, y) => dest.extend_from_slice(&[x, y]),
-                    Case::GRTEQ(x, y) => dest.extend_from_slice(&[x, y]),
-                    Case::LSREQ(x, y) => dest.extend_from_slice(&[x, y]),
-                }
-                dest.push(to);
-            }
-
-            Op::HALT => {}
-            Op::NOOP => {}
-        }
+        op.encode(dest);
     }
 }
 
@@ -549,7 +712,7 @@ fn test_parser() {
     };
     let tokens = lexer.lex();
 
-    let mut parser = Parser { input: tokens };
+    let mut parser = Parser::new(tokens);
     let recipe = parser.parse().unwrap();
     println!("{:?}", recipe);
 
@@ -557,3 +720,183 @@ fn test_parser() {
     to_bytes(recipe, &mut out);
     println!("{:?}", out);
 }
+
+#[test]
+fn test_wide_labels() {
+    let input = format!("{} label as far jmp to far", "mov 1 to $1000 ".repeat(100));
+    let lexer = lexer::Lexer { input: &input };
+    let tokens = lexer.lex();
+
+    let mut parser = Parser::new(tokens);
+    let recipe = parser.parse().unwrap();
+
+    let mut out = Vec::new();
+    to_bytes(recipe, &mut out);
+    assert_eq!(out.len(), 403);
+    assert_eq!(&out[400..], &[0x0F, 0x90, 0x01]); // jmp to 400
+}
+
+#[test]
+fn test_machine_profile() {
+    let parse = |input: &str, config| {
+        let tokens = lexer::Lexer { input }.lex();
+        let mut parser = Parser::with_config(tokens, config);
+        let result = parser
+            .parse()
+            .map(|_| ())
+            .map_err(|e| (e.cause.to_owned(), e.responsible.line));
+        result.map(|_| parser.labels)
+    };
+    let small = MachineConfig {
+        registers: 4,
+        memory_size: 256,
+        exit_register: 3,
+        load_address: 0x10,
+        entry: 0x10,
+    };
+
+    let labels = parse("mov 1 to z label as end halt", small).unwrap();
+    assert_eq!(labels["end"], 0x13);
+    assert_eq!(
+        parse("mov 1 to z \n mov 1 to a", small),
+        Err((
+            "Register is not available on this machine profile".to_owned(),
+            1
+        ))
+    );
+    assert_eq!(
+        parse("mov 1 to $300", small),
+        Err((
+            "Address lies outside of this machine profile's memory".to_owned(),
+            0
+        ))
+    );
+    assert!(parse(&"mov 1 to x ".repeat(100), small).is_err());
+}
+
+#[test]
+fn test_disassembler() {
+    fn compile(input: &str, config: MachineConfig) -> Vec<u8> {
+        let mut parser = Parser::with_config(lexer::Lexer { input }.lex(), config);
+        let mut out = Vec::new();
+        to_bytes(parser.parse().unwrap(), &mut out);
+        out
+    }
+
+    let examples = [
+        include_str!("../../examples/bubble_sort.code"),
+        include_str!("../../examples/dice.code"),
+        include_str!("../../examples/fibonacci.code"),
+        include_str!("../../examples/hello_world.code"),
+        include_str!("../../examples/print_hex.code"),
+        include_str!("../../examples/print_string.code"),
+        include_str!("../../examples/ticker.code"),
+    ];
+    for load_address in [0, 0x1234] {
+        let config = MachineConfig {
+            load_address,
+            entry: load_address,
+            ..MachineConfig::default()
+        };
+        for example in examples {
+            let binary = compile(example, config);
+            let source = disassembler::to_source(&binary, load_address).unwrap();
+            assert_eq!(compile(&source, config), binary, "{}", source);
+        }
+    }
+
+    let binary = compile(
+        "label as top mov $i to x jmp if a <= c to top mul x with 3 sub y from z jmp if !carry to end halt label as end",
+        MachineConfig::default(),
+    );
+    assert_eq!(
+        disassembler::to_source(&binary, 0).unwrap(),
+        "label as L_0x00\n    mov $i to x\n    jmp if a <= c to L_0x00\n    mul x with 3\n    sub y from z\n    \
+         jmp if !carry to L_0x14\n    halt\nlabel as L_0x14\n"
+    );
+    assert_eq!(
+        disassembler::listing(&binary[..9], 0),
+        "L_0x00:\n0x0000  be 01 07           mov $i to x\n0x0003  1f 04 04 06 00 00  jmp if a <= c to L_0x00\n"
+    );
+
+    assert_eq!(
+        disassembler::to_source(&[0x0F, 0x01, 0x00, 0xFF], 0),
+        Err(disassembler::DisassemblyError::BadTarget { pc: 0, target: 1 })
+    );
+    assert_eq!(
+        disassembler::to_source(&[0xFF, 0x42], 0x100),
+        Err(disassembler::DisassemblyError::Decode(
+            shared::DecodeError::UnknownOpcode {
+                pc: 0x101,
+                opcode: 0x42
+            }
+        ))
+    );
+    assert_eq!(
+        disassembler::to_source(&[0x00], 0),
+        Err(disassembler::DisassemblyError::NoSyntax {
+            pc: 0,
+            op: Op::NOOP
+        })
+    );
+}
+
+#[test]
+fn test_register_pairs() {
+    let compile = |input| {
+        let mut parser = Parser::new(lexer::Lexer { input }.lex());
+        let mut out = Vec::new();
+        to_bytes(parser.parse().map_err(|e| e.cause.to_owned())?, &mut out);
+        Ok::<_, String>(out)
+    };
+    let binary = compile("mov $y:x to a mov 7 to $b:i mov a to $n:z halt").unwrap();
+    assert_eq!(binary, [0xBF, 4, 2, 1, 0xEE, 5, 7, 7, 0xEF, 0, 3, 4, 0xFF]);
+    let source = disassembler::to_source(&binary, 0).unwrap();
+    assert_eq!(
+        source,
+        "    mov $y:x to a\n    mov 7 to $b:i\n    mov a to $n:z\n    halt\n"
+    );
+    assert_eq!(compile(&source), Ok(binary));
+
+    assert_eq!(
+        compile("mov $y:x to $1000"),
+        Err("Register pairs can't be moved to or from memory".to_owned())
+    );
+    assert_eq!(
+        compile("mov $a to $y:x"),
+        Err("Register pairs can't be moved to or from memory".to_owned())
+    );
+    assert_eq!(
+        compile("mov 1 to $y:"),
+        Err("Missing the low register of the pair after here".to_owned())
+    );
+    assert_eq!(
+        compile("mov 1 to $y:5"),
+        Err("Expected a register here".to_owned())
+    );
+}
+
+#[test]
+fn test_locations() {
+    let mut parser = Parser::with_config(
+        lexer::Lexer {
+            input: "mov 1 to x\n  label as top add 2 to x\n\n  jmp if x < y to top halt",
+        }
+        .lex(),
+        MachineConfig {
+            load_address: 0x10,
+            ..MachineConfig::default()
+        },
+    );
+    parser.parse().unwrap();
+    let location = |line, column| Location { line, column };
+    assert_eq!(
+        parser.locations,
+        vec![
+            (0x10, location(1, 1)),
+            (0x13, location(2, 16)),
+            (0x16, location(4, 3)),
+            (0x1C, location(4, 23)),
+        ]
+    );
+}
@@ -2,6 +2,7 @@ use std::{time::Instant, io::Write};
 
 use structopt::StructOpt;
 use colored::*;
+use shared::{config::MachineConfig, debuginfo::DebugInfo, executable::Executable, symbols::Symbols};
 
 #[derive(StructOpt)]
 struct Args {
@@ -9,19 +10,62 @@ struct Args {
     input: std::path::PathBuf,
     #[structopt(short="o", long="output", default_value="out.bin", parse(from_os_str))]
     output: std::path::PathBuf,
+    /// Also write the address of every label to this file
+    #[structopt(short="s", long="symbols", parse(from_os_str))]
+    symbols: Option<std::path::PathBuf>,
+    /// Number of registers of the target machine
+    #[structopt(long="registers", default_value="8")]
+    registers: u8,
+    /// Memory size of the target machine in bytes
+    #[structopt(long="memory", default_value="65536")]
+    memory: usize,
+    /// Register holding the exit code on halt
+    #[structopt(long="exit-register", default_value="6")]
+    exit_register: u8,
+    /// Address the program gets loaded at, labels are relative to it
+    #[structopt(long="load-address", default_value="0")]
+    load_address: u16,
+    /// Address execution starts at, the load address by default
+    #[structopt(long="entry")]
+    entry: Option<u16>,
+    /// Write a raw image, just the code, instead of an executable
+    #[structopt(long="raw")]
+    raw: bool,
+    /// Write a raw image that starts with a header describing the machine profile, so the machine picks it up. Unlike
+    /// an executable it has no data sections or symbols, for loaders that only want the code and its profile
+    #[structopt(long="header")]
+    header: bool,
+    /// Also write the source location of every instruction and the labels to this file
+    #[structopt(short="g", long="debug-info", parse(from_os_str))]
+    debug_info: Option<std::path::PathBuf>,
+    /// Leave the symbol table out of the executable
+    #[structopt(long="strip")]
+    strip: bool,
 }
 
 
 fn main() {
     let args = Args::from_args();
-    let input = std::fs::read_to_string(args.input).expect("Unable to read input file");
+    let input = std::fs::read_to_string(&args.input).expect("Unable to read input file");
     let mut output = std::fs::File::create(args.output).expect("Unable to create output file");
 
     let timer = Instant::now(); 
 
+    let config = MachineConfig {
+        registers: args.registers,
+        memory_size: args.memory,
+        exit_register: args.exit_register,
+        load_address: args.load_address,
+        entry: args.entry.unwrap_or(args.load_address),
+    };
+    if let Err(err) = config.validate() {
+        println!("{}", format!("ERROR: invalid machine profile: {}", err).red().bold());
+        std::process::exit(1);
+    }
+
     let tokens = compiler::lexer::Lexer { input: input.as_str() }.lex();
 
-    let mut parser = compiler::Parser { input: tokens };
+    let mut parser = compiler::Parser::with_config(tokens, config);
 
     let result = parser.parse();
 
@@ -31,10 +75,30 @@ fn main() {
         println!("{}{}", prefix.red().bold(), input.lines().nth(err.responsible.line).unwrap());
         println!("{}{} {}", " ".repeat(prefix.len() + err.responsible.range.start), "^".repeat(err.responsible.range.len()).red().bold(), err.cause.red().bold());
     } else if let Ok(recipe) = result {
-        let mut out = Vec::new();
-        compiler::to_bytes(recipe, &mut out);
+        let mut code = Vec::new();
+        compiler::to_bytes(recipe, &mut code);
+        let out = if args.header {
+            [&config.to_header()[..], &code].concat()
+        } else if args.raw {
+            code
+        } else {
+            Executable {
+                config,
+                code,
+                data: Vec::new(),
+                symbols: if args.strip { None } else { Some(Symbols::new(&parser.labels)) },
+            }
+            .to_bytes()
+        };
 
         output.write_all(out.as_slice()).unwrap();
+        if let Some(path) = args.symbols {
+            std::fs::write(path, Symbols::new(&parser.labels).to_text()).expect("Unable to write symbols file");
+        }
+        if let Some(path) = args.debug_info {
+            let info = DebugInfo::new(&args.input.to_string_lossy(), parser.locations.clone(), Symbols::new(&parser.labels));
+            std::fs::write(path, info.to_text()).expect("Unable to write debug info file");
+        }
         println!("{}", format!("Compilation successful! TIME: {} seconds", Instant::now().duration_since(timer).as_secs_f32()).bright_green().bold());
     }
 }
@@ -0,0 +1,56 @@
+//! Compares the interpreter and the cached engine on the examples, run with `cargo bench -p machine`
+
+use std::time::{Duration, Instant};
+
+use machine::{io::BufferIo, Engine, Machine};
+
+const EXAMPLES: &[(&str, &str)] = &[
+    ("fibonacci", include_str!("../../examples/fibonacci.code")),
+    ("bubble_sort", include_str!("../../examples/bubble_sort.code")),
+    ("print_hex", include_str!("../../examples/print_hex.code")),
+    ("print_string", include_str!("../../examples/print_string.code")),
+    ("hello_world", include_str!("../../examples/hello_world.code")),
+];
+
+const RUNS: u32 = 2000;
+
+fn assemble(src: &str) -> Vec<u8> {
+    let tokens = compiler::lexer::Lexer { input: src }.lex();
+    let recipe = compiler::Parser::new(tokens).parse().unwrap();
+    let mut out = Vec::new();
+    compiler::to_bytes(recipe, &mut out);
+    out
+}
+
+/// Runs `code` to completion, returning what the program printed and the time it took
+fn run(code: &[u8], engine: Engine) -> (Vec<u8>, Duration) {
+    let mut vm = Machine::with_io(BufferIo::default(), 256);
+    vm.set_engine(engine);
+    vm.load(code).unwrap();
+    let start = Instant::now();
+    vm.run();
+    (std::mem::take(&mut vm.io_mut().output), start.elapsed())
+}
+
+fn main() {
+    println!("{:<14} {:>14} {:>14} {:>8}", "example", "interpreter", "cached", "speedup");
+    for (name, src) in EXAMPLES {
+        let code = assemble(src);
+        let mut totals = [Duration::default(); 2];
+        for _ in 0..RUNS {
+            let (expected, interpreted) = run(&code, Engine::Interpreter);
+            let (output, cached) = run(&code, Engine::Cached);
+            assert_eq!(output, expected, "engines disagree on {}", name);
+            totals[0] += interpreted;
+            totals[1] += cached;
+        }
+        let [interpreted, cached] = totals;
+        println!(
+            "{:<14} {:>12.2?} {:>12.2?} {:>7.2}x",
+            name,
+            interpreted / RUNS,
+            cached / RUNS,
+            interpreted.as_secs_f64() / cached.as_secs_f64()
+        );
+    }
+}
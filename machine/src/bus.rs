@@ -0,0 +1,259 @@
+use std::ops::Range;
+
+use crate::io::Io;
+
+// where the CLI maps the built-in devices with `--devices`
+pub const CONSOLE_ADDRESS: usize = 0xFF00;
+pub const TIMER_ADDRESS: usize = 0xFF10;
+pub const RNG_ADDRESS: usize = 0xFF20;
+pub const INTERVAL_TIMER_ADDRESS: usize = 0xFF30;
+
+/// Interrupt vector raised by `IntervalTimer`
+pub const TIMER_VECTOR: u8 = 0;
+
+/// Hardware mapped into the address space. Offsets are relative to the start of the mapped range
+pub trait Device {
+    fn read(&mut self, offset: usize) -> u8;
+    fn write(&mut self, offset: usize, val: u8);
+
+    /// Called once after every executed instruction
+    fn tick(&mut self) {}
+
+    /// Polled after `tick` while interrupts are enabled. Returns the vector of a pending interrupt and forgets it
+    fn interrupt(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Routes memory accesses of the guest either to RAM or to a mapped device
+pub struct Bus {
+    ram: Vec<u8>,
+    devices: Vec<(Range<usize>, Box<dyn Device>)>,
+}
+
+impl Bus {
+    pub fn new(size: usize) -> Self {
+        Self {
+            ram: vec![0u8; size],
+            devices: Vec::new(),
+        }
+    }
+
+    /// Maps `device` over `range`, shadowing the RAM underneath it.
+    /// Panics if the range overlaps an already mapped device
+    pub fn map(&mut self, range: Range<usize>, device: Box<dyn Device>) {
+        if self.devices.iter().any(|(r, _)| r.start < range.end && range.start < r.end) {
+            panic!("device mapped over {:#06x}..{:#06x} overlaps another device", range.start, range.end);
+        }
+        self.devices.push((range, device));
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    pub fn len(&self) -> usize {
+        self.ram.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ram.is_empty()
+    }
+
+    /// Whether a device is mapped at `addr`
+    pub fn is_mapped(&self, addr: usize) -> bool {
+        self.devices.iter().any(|(range, _)| range.contains(&addr))
+    }
+
+    /// Returns `None` if nothing lives at `addr`
+    pub fn read(&mut self, addr: usize) -> Option<u8> {
+        match self.device_at(addr) {
+            Some((start, device)) => Some(device.read(addr - start)),
+            None => self.ram.get(addr).copied(),
+        }
+    }
+
+    /// Returns `None` if nothing lives at `addr`
+    pub fn write(&mut self, addr: usize, val: u8) -> Option<()> {
+        match self.device_at(addr) {
+            Some((start, device)) => device.write(addr - start, val),
+            None => *self.ram.get_mut(addr)? = val,
+        }
+        Some(())
+    }
+
+    pub fn tick(&mut self) {
+        for (_, device) in self.devices.iter_mut() {
+            device.tick();
+        }
+    }
+
+    /// First pending interrupt of the mapped devices
+    pub fn interrupt(&mut self) -> Option<u8> {
+        self.devices.iter_mut().find_map(|(_, device)| device.interrupt())
+    }
+
+    fn device_at(&mut self, addr: usize) -> Option<(usize, &mut Box<dyn Device>)> {
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(range, device)| (range.start, device))
+    }
+}
+
+/// Offset 0 prints a byte when written and reads the next input byte when read, 0 past the end of input.
+/// Offset 1 reads 1 once the input is exhausted
+pub struct Console<I: Io> {
+    io: I,
+    eof: bool,
+}
+
+impl<I: Io> Console<I> {
+    pub const SIZE: usize = 2;
+
+    pub fn new(io: I) -> Self {
+        Self { io, eof: false }
+    }
+}
+
+impl<I: Io> Device for Console<I> {
+    fn read(&mut self, offset: usize) -> u8 {
+        match offset {
+            0 => match self.io.read_byte() {
+                Ok(Some(byte)) => byte,
+                _ => {
+                    self.eof = true;
+                    0
+                }
+            },
+            _ => self.eof as u8,
+        }
+    }
+
+    fn write(&mut self, offset: usize, val: u8) {
+        if offset == 0 {
+            let _ = self.io.write_byte(val);
+        }
+    }
+}
+
+/// Counts executed instructions. Reading offset 0 latches the count, offsets 0 to 3 then read it
+/// least significant byte first. Writing anywhere resets it
+#[derive(Debug, Default)]
+pub struct Timer {
+    count: u32,
+    latched: u32,
+}
+
+impl Timer {
+    pub const SIZE: usize = 4;
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset: usize) -> u8 {
+        if offset == 0 {
+            self.latched = self.count;
+        }
+        self.latched.to_le_bytes()[offset % 4]
+    }
+
+    fn write(&mut self, _offset: usize, _val: u8) {
+        self.count = 0;
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+}
+
+/// Xorshift generator, each read returns a new pseudo-random byte. Writing a byte reseeds it
+#[derive(Debug)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub const SIZE: usize = 1;
+
+    pub fn new(seed: u8) -> Self {
+        Self {
+            state: 0x9E3779B9 ^ seed as u32, // never zero, which xorshift can't leave
+        }
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Device for Rng {
+    fn read(&mut self, _offset: usize) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 24) as u8
+    }
+
+    fn write(&mut self, _offset: usize, val: u8) {
+        *self = Self::new(val);
+    }
+}
+
+/// Raises `TIMER_VECTOR` every `period` executed instructions. Offsets 0 and 1 hold the period, least significant
+/// byte first, a period of 0 stops it. Writing restarts the count
+#[derive(Debug, Default)]
+pub struct IntervalTimer {
+    period: u16,
+    count: u16,
+    pending: bool,
+}
+
+impl IntervalTimer {
+    pub const SIZE: usize = 2;
+
+    pub fn new(period: u16) -> Self {
+        Self {
+            period,
+            ..Self::default()
+        }
+    }
+}
+
+impl Device for IntervalTimer {
+    fn read(&mut self, offset: usize) -> u8 {
+        self.period.to_le_bytes()[offset % 2]
+    }
+
+    fn write(&mut self, offset: usize, val: u8) {
+        let mut bytes = self.period.to_le_bytes();
+        bytes[offset % 2] = val;
+        self.period = u16::from_le_bytes(bytes);
+        self.count = 0;
+    }
+
+    fn tick(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.count += 1;
+        if self.count >= self.period {
+            self.count = 0;
+            self.pending = true;
+        }
+    }
+
+    fn interrupt(&mut self) -> Option<u8> {
+        if self.pending {
+            self.pending = false;
+            Some(TIMER_VECTOR)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+use shared::{symbols::Symbols, CAddress, Op, REGISTER_NAMES};
+
+use crate::{
+    history::DEFAULT_HISTORY_LIMIT,
+    io::Io,
+    source::SourceMap,
+    watch::{Access, Condition, Target, Trigger, Watchpoint},
+    Machine, State, MAX_INSTRUCTION_SIZE, FLAG_CARRY, FLAG_INTERRUPT, FLAG_NEGATIVE, FLAG_OVERFLOW, FLAG_ZERO,
+};
+
+/// `println!` to the debugger's output
+macro_rules! say {
+    ($self:ident) => {
+        writeln!($self.out).expect("Unable to write debugger output")
+    };
+    ($self:ident, $($arg:tt)*) => {
+        writeln!($self.out, $($arg)*).expect("Unable to write debugger output")
+    };
+}
+
+const HELP: &str = "\
+step [n]            execute n instructions (s)
+continue            run until a breakpoint, a brk instruction or the end (c)
+back [n]            undo the last n instructions (sb)
+rcontinue           run backwards until a breakpoint or the start of the history (rc)
+writer addr         show which instruction last wrote to an address (w)
+break [addr|label]  set a breakpoint, or list them without argument (b)
+delete addr|label   remove a breakpoint (d)
+watch [target [how]] watch a register, address or label, or list watchpoints without argument. how is
+                    read, write (default), or a condition on the new value: == n, != n, < n or > n
+unwatch id          remove a watchpoint
+regs                show registers, flags, sp and pc (r)
+set reg|pc|flags n  change a register, pc or the flags
+mem addr [len]      dump memory (m)
+poke addr byte      write a byte to memory
+dis [addr] [count]  disassemble, around pc by default
+quit                stop debugging (q)";
+
+/// Executes one instruction for the debugger, see `Debugger::set_stepper`
+pub type Stepper<'a, I> = Box<dyn FnMut(&mut Machine<I>) + 'a>;
+
+/// Interactive prompt driving a machine one instruction at a time
+pub struct Debugger<'a, I: Io> {
+    vm: &'a mut Machine<I>,
+    symbols: Symbols,
+    source: Option<SourceMap>,
+    breakpoints: BTreeSet<usize>,
+    out: Box<dyn Write + 'a>,
+    stepper: Stepper<'a, I>,
+}
+
+impl<'a, I: Io> Debugger<'a, I> {
+    /// A debugger printing to stdout
+    pub fn new(vm: &'a mut Machine<I>, symbols: Symbols) -> Self {
+        Self::with_output(vm, symbols, io::stdout())
+    }
+
+    /// A debugger printing to `out` instead of stdout
+    pub fn with_output(vm: &'a mut Machine<I>, symbols: Symbols, out: impl Write + 'a) -> Self {
+        vm.set_history_limit(Some(DEFAULT_HISTORY_LIMIT));
+        Self {
+            vm,
+            symbols,
+            source: None,
+            breakpoints: BTreeSet::new(),
+            out: Box::new(out),
+            stepper: Box::new(|vm| {
+                vm.step();
+            }),
+        }
+    }
+
+    /// Executes instructions through `stepper` instead of `Machine::step`, to trace or profile them for instance
+    pub fn set_stepper(&mut self, stepper: impl FnMut(&mut Machine<I>) + 'a) {
+        self.stepper = Box::new(stepper);
+    }
+
+    /// Shows the source line of every instruction the debugger prints
+    pub fn set_source(&mut self, source: SourceMap) {
+        self.source = Some(source);
+    }
+
+    /// Reads commands from stdin until the user quits or stdin is closed
+    pub fn run(&mut self) -> State {
+        self.show_location();
+        loop {
+            write!(self.out, "(vrrmm) ").expect("Unable to write debugger output");
+            self.out.flush().expect("Unable to write debugger output");
+
+            // read a line at a time, the guest may read stdin in between
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            match self.command(line.trim()) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(msg) => say!(self, "{}", msg),
+            }
+        }
+        self.vm.state()
+    }
+
+    /// Like `run`, for a program that just executed the brk instruction at `pc`
+    pub fn run_from_brk(&mut self, pc: usize) -> State {
+        say!(self, "brk at {}", self.describe(pc));
+        self.run()
+    }
+
+    /// Runs a single command line, returns whether the user asked to quit
+    pub fn command(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Ok(false),
+        };
+        let args: Vec<&str> = words.collect();
+
+        match cmd {
+            "s" | "step" => {
+                let n = match args.first() {
+                    Some(n) => parse_number(n)?,
+                    None => 1,
+                };
+                for _ in 0..n {
+                    if self.vm.is_done() {
+                        break;
+                    }
+                    (self.stepper)(self.vm);
+                    if self.show_watch_hits() {
+                        break;
+                    }
+                }
+                self.show_location();
+            }
+            "c" | "continue" => {
+                self.resume();
+                self.show_location();
+            }
+            "sb" | "back" => {
+                let n = match args.first() {
+                    Some(n) => parse_number(n)?,
+                    None => 1,
+                };
+                for _ in 0..n {
+                    if !self.vm.step_back() {
+                        say!(self, "reached the start of the history");
+                        break;
+                    }
+                }
+                self.show_location();
+            }
+            "rc" | "rcontinue" => {
+                self.resume_backwards();
+                self.show_location();
+            }
+            "w" | "writer" => {
+                let addr = self.address(args.first().ok_or("missing address")?)?;
+                match self.vm.last_write(addr) {
+                    Some(write) => say!(
+                        self,
+                        "{:#06x} last written at step {} by {}: {:#04x} -> {:#04x}",
+                        addr,
+                        write.step,
+                        self.describe(write.pc),
+                        write.old,
+                        write.new
+                    ),
+                    None => say!(self, "{:#06x} wasn't written within the history", addr),
+                }
+            }
+            "b" | "break" => match args.first() {
+                Some(arg) => {
+                    let addr = self.address(arg)?;
+                    self.breakpoints.insert(addr);
+                    say!(self, "breakpoint at {}", self.describe(addr));
+                }
+                None => {
+                    for addr in self.breakpoints.iter() {
+                        say!(self, "{}", self.describe(*addr));
+                    }
+                }
+            },
+            "d" | "delete" => {
+                let addr = self.address(args.first().ok_or("missing address")?)?;
+                if !self.breakpoints.remove(&addr) {
+                    return Err(format!("no breakpoint at {}", self.describe(addr)));
+                }
+            }
+            "watch" => match args.first() {
+                Some(arg) => {
+                    let target = match REGISTER_NAMES.iter().position(|name| name == arg) {
+                        Some(reg) => Target::Register(reg as u8),
+                        None => Target::Memory(self.address(arg)?),
+                    };
+                    let trigger = match (args.get(1), args.get(2)) {
+                        (None, _) | (Some(&"write"), None) => Trigger::Write,
+                        (Some(&"read"), None) => Trigger::Read,
+                        (Some(op), Some(val)) => {
+                            let val = byte(parse_number(val)?)?;
+                            Trigger::Change(match *op {
+                                "==" => Condition::Equal(val),
+                                "!=" => Condition::NotEqual(val),
+                                "<" => Condition::Less(val),
+                                ">" => Condition::Greater(val),
+                                _ => return Err(format!("unknown condition '{}'", op)),
+                            })
+                        }
+                        (Some(how), None) => return Err(format!("expected read, write or a condition, got '{}'", how)),
+                    };
+                    let id = self.vm.add_watchpoint(Watchpoint { target, trigger });
+                    say!(self, "watchpoint {} on {}", id, target);
+                }
+                None => {
+                    for (id, watchpoint) in self.vm.watchpoints() {
+                        say!(self, "{}: {} {:?}", id, watchpoint.target, watchpoint.trigger);
+                    }
+                }
+            },
+            "unwatch" => {
+                let id = parse_number(args.first().ok_or("missing watchpoint id")?)?;
+                if !self.vm.remove_watchpoint(id) {
+                    return Err(format!("no watchpoint {}", id));
+                }
+            }
+            "r" | "regs" => self.show_registers(),
+            "set" => {
+                let target = *args.first().ok_or("missing register, pc or flags")?;
+                let val = *args.get(1).ok_or("missing value")?;
+                match target {
+                    "pc" => {
+                        let pc = self.address(val)?;
+                        if pc >= self.vm.memory().len() {
+                            return Err(format!("{:#06x} lies outside of memory", pc));
+                        }
+                        self.vm.set_pc(pc);
+                    }
+                    "flags" => self.vm.set_flags(byte(parse_number(val)?)?),
+                    _ => {
+                        let reg = REGISTER_NAMES
+                            .iter()
+                            .position(|name| *name == target)
+                            .filter(|reg| *reg < self.vm.registers().len())
+                            .ok_or(format!("unknown register '{}'", target))?;
+                        self.vm.set_register(reg as u8, byte(parse_number(val)?)?);
+                    }
+                }
+            }
+            "m" | "mem" => {
+                let addr = self.address(args.first().ok_or("missing address")?)?;
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len)?,
+                    None => 16,
+                };
+                self.dump(addr, len);
+            }
+            "poke" => {
+                let addr = self.address(args.first().ok_or("missing address")?)?;
+                let val = byte(parse_number(args.get(1).ok_or("missing value")?)?)?;
+                let cell = self.vm.memory_mut().get_mut(addr).ok_or("address out of memory")?;
+                *cell = val;
+            }
+            "dis" => {
+                let count = match args.get(1) {
+                    Some(count) => parse_number(count)?,
+                    None => 8,
+                };
+                match args.first() {
+                    Some(arg) => self.disassemble(self.address(arg)?, count),
+                    None => {
+                        let pc = self.vm.pc().ok_or("machine is not running")?;
+                        let (start, before) = self.preceding(pc, 3);
+                        self.disassemble(start, before + count);
+                    }
+                }
+            }
+            "h" | "help" => say!(self, "{}", HELP),
+            "q" | "quit" => return Ok(true),
+            _ => return Err(format!("unknown command '{}', try 'help'", cmd)),
+        }
+        Ok(false)
+    }
+
+    /// Runs until a breakpoint or a brk instruction is reached, or the machine stops
+    fn resume(&mut self) {
+        while let (false, Some(pc)) = (self.vm.is_done(), self.vm.pc()) {
+            let brk = matches!(self.vm.decode(pc), Ok(Op::BRK));
+            (self.stepper)(self.vm);
+            if self.show_watch_hits() {
+                break;
+            }
+            if brk {
+                say!(self, "brk at {}", self.describe(pc));
+                break;
+            }
+            if let Some(pc) = self.vm.pc() {
+                if self.breakpoints.contains(&pc) {
+                    say!(self, "breakpoint at {}", self.describe(pc));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Steps back until pc is on a breakpoint or the history runs out
+    fn resume_backwards(&mut self) {
+        while self.vm.step_back() {
+            if let Some(pc) = self.vm.pc() {
+                if self.breakpoints.contains(&pc) {
+                    say!(self, "breakpoint at {}", self.describe(pc));
+                    return;
+                }
+            }
+        }
+        say!(self, "reached the start of the history");
+    }
+
+    /// Prints the watchpoints fired by the last step, returns whether they paused the machine
+    fn show_watch_hits(&mut self) -> bool {
+        for hit in self.vm.watch_hits() {
+            let access = match hit.access {
+                Access::Read => format!("read {:#04x}", hit.new),
+                Access::Write => format!("{:#04x} -> {:#04x}", hit.old, hit.new),
+            };
+            say!(self, "watchpoint {} on {} at {}: {}", hit.id, hit.target, self.describe(hit.pc), access);
+        }
+        self.vm.paused()
+    }
+
+    fn show_location(&mut self) {
+        match self.vm.state() {
+            State::Running { pc } if !self.vm.is_done() => self.disassemble(pc, 1),
+            State::Running { .. } => say!(self, "reached the end of the program"),
+            State::Halted(code) => say!(self, "halted with exit code {}", code),
+            State::Faulted(fault) => {
+                say!(self, "faulted: {}", fault);
+                self.show_source(fault.pc());
+            }
+            State::OutOfFuel { pc } => {
+                say!(self, "ran out of fuel at {}", self.describe(pc));
+                self.show_source(pc);
+            }
+            State::Null => say!(self, "nothing loaded"),
+        }
+    }
+
+    fn show_registers(&mut self) {
+        for (name, val) in REGISTER_NAMES.iter().zip(self.vm.registers()) {
+            write!(self.out, "{}={:#04x} ", name, val).expect("Unable to write debugger output");
+        }
+        say!(self);
+
+        let flags = self.vm.flags();
+        let flag = |bit, name| if flags & bit != 0 { name } else { "-" };
+        say!(self, 
+            "flags={}{}{}{}{} sp={} pc={}",
+            flag(FLAG_ZERO, "Z"),
+            flag(FLAG_CARRY, "C"),
+            flag(FLAG_OVERFLOW, "V"),
+            flag(FLAG_NEGATIVE, "N"),
+            flag(FLAG_INTERRUPT, "I"),
+            self.vm.sp(),
+            match self.vm.pc() {
+                Some(pc) => self.describe(pc),
+                None => "-".to_owned(),
+            }
+        );
+    }
+
+    fn dump(&mut self, addr: usize, len: usize) {
+        let memory = self.vm.memory();
+        let end = addr.saturating_add(len).min(memory.len());
+        for row in (addr..end).step_by(16) {
+            let bytes = &memory[row..(row + 16).min(end)];
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|b| if b.is_ascii_graphic() { *b as char } else { '.' })
+                .collect();
+            say!(self, "{:#06x}  {:<48} {}", row, hex.join(" "), ascii);
+        }
+    }
+
+    fn disassemble(&mut self, mut addr: usize, count: usize) {
+        for _ in 0..count {
+            let symbols = &self.symbols;
+            if let Some(label) = CAddress::try_from(addr).ok().and_then(|addr| symbols.label_at(addr)) {
+                say!(self, "{}:", label);
+            }
+            self.show_source(addr);
+            let marker = if Some(addr) == self.vm.pc() { "=>" } else { "  " };
+            match self.vm.decode(addr) {
+                Ok(op) => {
+                    let bytes: Vec<String> = self.vm.memory()[addr..addr + op.get_size()]
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect();
+                    say!(self, "{} {:#06x}  {:<18} {:?}", marker, addr, bytes.join(" "), op);
+                    addr += op.get_size();
+                }
+                Err(fault) => {
+                    say!(self, "{} {:#06x}  {}", marker, addr, fault);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Start of the `n` instructions before `addr` and how many were found, fewer when they can't be told apart.
+    /// Decoding backwards is ambiguous, so this looks for a start from which decoding lands right on `addr`,
+    /// trying the start of the program first
+    fn preceding(&self, addr: usize, n: usize) -> (usize, usize) {
+        let program = self.vm.code_range().filter(|code| code.contains(&addr)).map(|code| code.start);
+        for start in program.into_iter().chain(addr.saturating_sub(n * MAX_INSTRUCTION_SIZE)..addr) {
+            let mut found = Vec::new();
+            let mut at = start;
+            while at < addr {
+                match self.vm.decode(at) {
+                    Ok(op) => {
+                        found.push(at);
+                        at += op.get_size();
+                    }
+                    Err(_) => break,
+                }
+            }
+            if at == addr {
+                let found = &found[found.len().saturating_sub(n)..];
+                return (found.first().copied().unwrap_or(addr), found.len());
+            }
+        }
+        (addr, 0)
+    }
+
+    /// `file:line:column: text` of the instruction at `addr`, if the debug info knows it
+    fn show_source(&mut self, addr: usize) {
+        if let Some(line) = self.source.as_ref().and_then(|source| source.describe(addr)) {
+            say!(self, "   {}", line);
+        }
+    }
+
+    /// Address followed by the closest label, like `0x001c <print_hex+4>`
+    fn describe(&self, addr: usize) -> String {
+        // addresses past 16 bits can't have a label
+        match CAddress::try_from(addr).ok().and_then(|addr| self.symbols.nearest(addr)) {
+            Some((start, label)) if addr == start as usize => format!("{:#06x} <{}>", addr, label),
+            Some((start, label)) => format!("{:#06x} <{}+{}>", addr, label, addr - start as usize),
+            None => format!("{:#06x}", addr),
+        }
+    }
+
+    fn address(&self, arg: &str) -> Result<usize, String> {
+        match self.symbols.address_of(arg) {
+            Some(addr) => Ok(addr as usize),
+            None => parse_number(arg).map_err(|_| format!("'{}' is neither an address nor a label", arg)),
+        }
+    }
+}
+
+/// Decimal, or hexadecimal with a `0x` prefix
+fn parse_number(s: &str) -> Result<usize, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("'{}' is not a number", s))
+}
+
+fn byte(val: usize) -> Result<u8, String> {
+    u8::try_from(val).map_err(|_| format!("{} does not fit in a byte", val))
+}
@@ -0,0 +1,42 @@
+use shared::Register;
+
+use crate::State;
+
+/// Steps the debugger keeps by default. A step is 136 bytes plus a small allocation for each kind of change it made,
+/// 16 bytes for a register or the stack and 64 for memory, so typically 15 to 20 megabytes in total
+pub const DEFAULT_HISTORY_LIMIT: usize = 100_000;
+
+/// How a step changed the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StackChange {
+    Push,
+    /// Holds the popped value
+    Pop(u8),
+}
+
+/// What a step overwrote, so it can be undone
+#[derive(Debug, Clone)]
+pub(crate) struct Undo {
+    pub(crate) state: State,
+    /// (register, old value) of every write to a register, in order
+    pub(crate) registers: Vec<(Register, u8)>,
+    pub(crate) flags: u8,
+    /// Every push and pop, in order
+    pub(crate) stack: Vec<StackChange>,
+    pub(crate) steps: u64,
+    pub(crate) cycles: u64,
+    pub(crate) fuel: Option<u64>,
+    /// (address, old value, new value) of every write to RAM, in order
+    pub(crate) memory: Vec<(usize, u8, u8)>,
+}
+
+/// A write to RAM found in the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    /// Number of steps executed before the writing instruction
+    pub step: u64,
+    /// Address of the writing instruction
+    pub pc: usize,
+    pub old: u8,
+    pub new: u8,
+}
@@ -0,0 +1,140 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    rc::Rc,
+};
+
+/// Console of the machine, where `print` writes to and `read` reads from
+pub trait Io {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    /// Returns `None` once the input is exhausted
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<I: Io + ?Sized> Io for Box<I> {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        (**self).write_byte(byte)
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        (**self).read_byte()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Shares one console between the machine and a console device, for instance
+impl<I: Io + ?Sized> Io for Rc<RefCell<I>> {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.borrow_mut().write_byte(byte)
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        self.borrow_mut().read_byte()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.borrow_mut().flush()
+    }
+}
+
+/// Prints to stdout and reads from stdin, bytes as they are
+#[derive(Debug, Default)]
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        io::stdout().flush()?; // show any prompt before blocking
+        read_one(&mut io::stdin())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Keeps the console in memory, handy for tests and embedders
+#[derive(Debug, Default)]
+pub struct BufferIo {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl BufferIo {
+    pub fn new(input: &[u8]) -> Self {
+        Self {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Io for BufferIo {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.output.push(byte);
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.input.pop_front())
+    }
+}
+
+/// Reads input from any reader and writes output to any writer
+pub struct StreamIo<R, W> {
+    pub input: R,
+    pub output: W,
+}
+
+impl<R: Read, W: Write> Io for StreamIo<R, W> {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.output.write_all(&[byte])
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        self.output.flush()?;
+        read_one(&mut self.input)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+pub type FileIo = StreamIo<BufReader<File>, BufWriter<File>>;
+
+impl FileIo {
+    /// Reads input from the file at `input` and (over)writes output to the file at `output`
+    pub fn open(input: &Path, output: &Path) -> io::Result<Self> {
+        Ok(Self {
+            input: BufReader::new(File::open(input)?),
+            output: BufWriter::new(File::create(output)?),
+        })
+    }
+}
+
+fn read_one(reader: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,1993 @@
+pub mod bus;
+pub mod debugger;
+pub mod history;
+pub mod io;
+pub mod profile;
+pub mod snapshot;
+pub mod source;
+pub mod summary;
+pub mod trace;
+pub mod watch;
+
+use std::{cell::Cell, collections::VecDeque, convert::TryFrom, fmt, ops::Range};
+
+use bus::Bus;
+use history::{MemoryWrite, StackChange, Undo};
+use io::{Io, StdIo};
+use snapshot::{Snapshot, SnapshotError};
+use trace::TraceRecord;
+use watch::{Access, Target, WatchAction, WatchCallback, WatchHit, Watchpoint};
+use shared::{config::MachineConfig, executable::{Executable, ExecutableError}, *};
+
+/// Default memory size, enough to cover the whole 16-bit address space
+pub const DEFAULT_MEMORY_SIZE: usize = 0x10000;
+
+/// Number of bytes the call stack can hold
+pub const STACK_SIZE: usize = 64;
+
+// bits of the flags register, set by ALU operations
+pub const FLAG_ZERO: u8 = 1 << 0;
+pub const FLAG_CARRY: u8 = 1 << 1; // also the borrow of subtractions
+pub const FLAG_OVERFLOW: u8 = 1 << 2;
+pub const FLAG_NEGATIVE: u8 = 1 << 3;
+/// Set by `ei` and cleared by `di`, left alone by ALU operations
+pub const FLAG_INTERRUPT: u8 = 1 << 4;
+
+/// Number of interrupt vectors. Their handler addresses sit at the very end of memory, two bytes each
+pub const VECTOR_COUNT: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running {pc: usize},
+    Halted(u8), // error code
+    Faulted(Fault),
+    OutOfFuel {pc: usize}, // the step budget ran out before the instruction at pc
+    Null,
+}
+
+/// What `load` protects of the memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protection {
+    /// The program's bytes can't be written by the program itself
+    pub read_only_code: bool,
+    /// Memory outside of the program's bytes can't be executed
+    pub non_executable_data: bool,
+}
+
+impl Protection {
+    pub const NONE: Self = Self { read_only_code: false, non_executable_data: false };
+    pub const FULL: Self = Self { read_only_code: true, non_executable_data: true };
+}
+
+impl Default for Protection {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+/// Longest instruction, a conditional jump comparing two registers
+pub const MAX_INSTRUCTION_SIZE: usize = 6;
+
+/// How instructions are fetched. Both engines give identical results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Decodes every instruction from memory each time it runs
+    Interpreter,
+    /// Keeps decoded instructions per address, forgetting them when their bytes get written to
+    Cached,
+}
+
+impl std::str::FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpreter" => Ok(Self::Interpreter),
+            "cached" => Ok(Self::Cached),
+            _ => Err(format!("unknown engine '{}', expected 'interpreter' or 'cached'", s)),
+        }
+    }
+}
+
+/// Number of cycles an instruction takes. Memory accesses and the slower ALU operations cost more
+pub fn cycle_cost(op: Op) -> u64 {
+    match op {
+        Op::NOOP | Op::BRK | Op::HALT => 1,
+
+        Op::MOVRN(..) | Op::MOVRR(..) => 1,
+        Op::MOVRA(..) | Op::MOVRX(..) | Op::MOVAN(..) | Op::MOVAR(..) | Op::MOVXN(..) | Op::MOVXR(..) => 3,
+        Op::MOVAA(..) | Op::MOVAX(..) | Op::MOVXA(..) | Op::MOVXX(..) => 5,
+        Op::MOVRP(..) | Op::MOVPN(..) | Op::MOVPR(..) => 3,
+
+        Op::ADDRN(..) | Op::ADDRR(..) | Op::ADCRN(..) | Op::ADCRR(..) => 1,
+        Op::SUBRN(..) | Op::SUBRR(..) | Op::SBBRN(..) | Op::SBBRR(..) => 1,
+        Op::ANDRR(..) | Op::ANDRN(..) | Op::XORRR(..) | Op::XORRN(..) | Op::ORRR(..) | Op::ORRN(..) => 1,
+        Op::SHR(_) | Op::SHL(_) => 1,
+        Op::MULRN(..) | Op::MULRR(..) => 4,
+        Op::DIVRN(..) | Op::DIVRR(..) | Op::MODRN(..) | Op::MODRR(..) => 8,
+
+        Op::PUSH(_) | Op::POP(_) => 2,
+        Op::PRINT(_) | Op::READ(_) => 4,
+
+        Op::JMP(_) | Op::JMPIF(..) => 2,
+        Op::CALL(_) | Op::RET => 3,
+
+        Op::EI | Op::DI => 1,
+        Op::RETI => 3,
+    }
+}
+
+/// A runtime error raised by the guest program. `pc` is the address of the faulting instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    IllegalOpcode { pc: usize, opcode: u8 },
+    IllegalCondition { pc: usize, code: u8 },
+    BadRegister { pc: usize, reg: Register },
+    PcOutOfRange { pc: usize },
+    AddressOutOfRange { pc: usize, addr: usize },
+    DivideByZero { pc: usize },
+    IoError { pc: usize },
+    StackOverflow { pc: usize },
+    StackUnderflow { pc: usize },
+    WriteProtected { pc: usize, addr: usize },
+    NotExecutable { pc: usize },
+    /// Fetching while the machine isn't running, `pc` is the last instruction it executed
+    NotRunning { pc: usize },
+}
+
+impl Fault {
+    pub fn pc(&self) -> usize {
+        match *self {
+            Fault::IllegalOpcode { pc, .. } => pc,
+            Fault::IllegalCondition { pc, .. } => pc,
+            Fault::BadRegister { pc, .. } => pc,
+            Fault::PcOutOfRange { pc } => pc,
+            Fault::AddressOutOfRange { pc, .. } => pc,
+            Fault::DivideByZero { pc } => pc,
+            Fault::IoError { pc } => pc,
+            Fault::StackOverflow { pc } => pc,
+            Fault::StackUnderflow { pc } => pc,
+            Fault::WriteProtected { pc, .. } => pc,
+            Fault::NotExecutable { pc } => pc,
+            Fault::NotRunning { pc } => pc,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::IllegalOpcode { pc, opcode } => write!(f, "illegal opcode {:#04x} at {:#04x}", opcode, pc),
+            Fault::IllegalCondition { pc, code } => write!(f, "illegal condition code {:#04x} at {:#04x}", code, pc),
+            Fault::BadRegister { pc, reg } => write!(f, "bad register {:#04x} at {:#04x}", reg, pc),
+            Fault::PcOutOfRange { pc } => write!(f, "instruction at {:#04x} runs past the end of memory", pc),
+            Fault::AddressOutOfRange { pc, addr } => write!(f, "address {:#04x} accessed at {:#04x} is out of memory", addr, pc),
+            Fault::DivideByZero { pc } => write!(f, "division by zero at {:#04x}", pc),
+            Fault::IoError { pc } => write!(f, "console i/o failed at {:#04x}", pc),
+            Fault::StackOverflow { pc } => write!(f, "stack overflow at {:#04x}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "stack underflow at {:#04x}", pc),
+            Fault::WriteProtected { pc, addr } => write!(f, "instruction at {:#04x} writes to read-only code at {:#04x}", pc, addr),
+            Fault::NotExecutable { pc } => write!(f, "{:#04x} is data and can't be executed", pc),
+            Fault::NotRunning { pc } => write!(f, "fetched after {:#04x} while the machine isn't running", pc),
+        }
+    }
+}
+
+impl From<DecodeError> for Fault {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::UnknownOpcode { pc, opcode } => Fault::IllegalOpcode { pc, opcode },
+            DecodeError::UnknownCondition { pc, code } => Fault::IllegalCondition { pc, code },
+            DecodeError::Truncated { pc } => Fault::PcOutOfRange { pc },
+        }
+    }
+}
+
+/// Side effects collected while tracing an instruction or recording it in the history
+#[derive(Default)]
+struct Journal {
+    registers: Vec<(Register, u8)>,
+    stack: Vec<StackChange>,
+    memory: Vec<(usize, u8, u8)>,
+    output: Option<u8>,
+}
+
+pub struct Machine<I: Io = StdIo> {
+   config: MachineConfig,
+   registers: Vec<u8>,
+   bus: Bus,
+   stack: Vec<u8>, // grows upwards, sp is its length
+   flags: u8,
+   state: State,
+   ins_pc: usize, // address of the instruction being executed
+   io: I,
+   journal: Option<Journal>,
+   fuel: Option<u64>, // steps left before running out, unlimited if None
+   steps: u64,
+   cycles: u64,
+   history: Option<(VecDeque<Undo>, usize)>, // undo log and how many steps it keeps
+   engine: Engine,
+   cache: Vec<Option<Op>>, // decoded instructions by address, empty until the cached engine fetches
+   protection: Protection,
+   code: Option<Range<usize>>, // where the program was loaded, None if unknown
+   watchpoints: Vec<Option<Watchpoint>>, // indexed by id
+   watch_callback: Option<WatchCallback>,
+   accesses: Vec<(Target, Access, u8, u8)>, // of the current instruction, only collected while watching
+   register_reads: Cell<Vec<(Register, u8)>>, // apart because `reg` only borrows self
+   watch_hits: Vec<WatchHit>,
+   paused: bool,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self::with_memory_size(DEFAULT_MEMORY_SIZE)
+    }
+
+    pub fn with_memory_size(size: usize) -> Self {
+        Self::with_io(StdIo, size)
+    }
+}
+
+impl<I: Io> Machine<I> {
+    pub fn with_io(io: I, memory_size: usize) -> Self {
+        Self::with_config(io, MachineConfig { memory_size, ..MachineConfig::default() })
+    }
+
+    /// Panics if `config` doesn't validate
+    pub fn with_config(io: I, config: MachineConfig) -> Self {
+        if let Err(err) = config.validate() {
+            panic!("invalid machine profile: {}", err);
+        }
+        Self {
+            config,
+            registers: vec![0u8; config.registers as usize],
+            bus: Bus::new(config.memory_size),
+            stack: Vec::with_capacity(STACK_SIZE),
+            flags: 0,
+            state: State::Null,
+            ins_pc: 0,
+            io,
+            journal: None,
+            fuel: None,
+            steps: 0,
+            cycles: 0,
+            history: None,
+            engine: Engine::Interpreter,
+            cache: Vec::new(),
+            protection: Protection::default(),
+            code: None,
+            watchpoints: Vec::new(),
+            watch_callback: None,
+            accesses: Vec::new(),
+            register_reads: Cell::new(Vec::new()),
+            watch_hits: Vec::new(),
+            paused: false,
+        }
+    }
+
+    pub fn config(&self) -> &MachineConfig {
+        &self.config
+    }
+
+    pub fn io(&self) -> &I {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.cache.clear();
+        &mut self.bus
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.cache.clear();
+    }
+
+    pub fn protection(&self) -> Protection {
+        self.protection
+    }
+
+    /// Allows self-modifying code or running data, for instance
+    pub fn set_protection(&mut self, protection: Protection) {
+        self.protection = protection;
+    }
+
+    /// The bytes of the loaded program, protected according to `protection`. None until a program is loaded, or after
+    /// restoring a snapshot that didn't record it, in which case nothing is protected
+    pub fn code_range(&self) -> Option<Range<usize>> {
+        self.code.clone()
+    }
+
+    /// Returns the id of the new watchpoint
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(Some(watchpoint));
+        self.watchpoints.len() - 1
+    }
+
+    /// Returns false if there was no watchpoint with that id
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let removed = self.watchpoints.get_mut(id).and_then(|w| w.take()).is_some();
+        if self.watchpoints.iter().all(|w| w.is_none()) {
+            self.watchpoints.clear();
+        }
+        removed
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints.iter().enumerate().filter_map(|(id, w)| Some((id, w.as_ref()?)))
+    }
+
+    /// Called for every watchpoint that fires. Without a callback the machine pauses
+    pub fn set_watch_callback(&mut self, callback: Option<WatchCallback>) {
+        self.watch_callback = callback;
+    }
+
+    /// Watchpoints fired by the last step
+    pub fn watch_hits(&self) -> &[WatchHit] {
+        &self.watch_hits
+    }
+
+    /// Whether a watchpoint asked to pause after the last step. `run` and `run_for` return early when it does
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    fn watching(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    fn check_watchpoints(&mut self) {
+        let mut accesses = std::mem::take(&mut self.accesses);
+        let mut reads = self.register_reads.take();
+        for (reg, val) in reads.drain(..) {
+            accesses.push((Target::Register(reg), Access::Read, val, val));
+        }
+        self.register_reads.set(reads);
+
+        let mut hits = Vec::new();
+        for (target, access, old, mut new) in accesses.drain(..) {
+            if let (Target::Register(reg), Access::Write) = (target, access) {
+                new = self.registers[reg as usize];
+            }
+            for (id, watchpoint) in self.watchpoints.iter().enumerate() {
+                match watchpoint {
+                    Some(watchpoint) if watchpoint.matches(target, access, old, new) => {
+                        hits.push(WatchHit { id, pc: self.ins_pc, target, access, old, new });
+                    },
+                    _ => {},
+                }
+            }
+        }
+        for hit in hits.iter() {
+            let action = match self.watch_callback {
+                Some(ref mut callback) => callback(hit),
+                None => WatchAction::Pause,
+            };
+            self.paused |= action == WatchAction::Pause;
+        }
+        self.watch_hits = hits;
+        self.accesses = accesses;
+    }
+
+    /// Drops the cached instructions that `addr` is part of
+    fn invalidate(&mut self, addr: usize) {
+        let start = addr.saturating_sub(MAX_INSTRUCTION_SIZE - 1);
+        for entry in self.cache.iter_mut().take(addr + 1).skip(start) {
+            *entry = None;
+        }
+    }
+
+    /// Clears memory, registers, stack and flags, copies `code` to the load address and points pc at the entry. Fuel
+    /// goes back to unlimited. Fails without touching the machine if the code doesn't fit in memory
+    pub fn load(&mut self, code: &[u8]) -> Result<(), ExecutableError> {
+        let start = self.config.load_address as usize;
+        if start + code.len() > self.bus.len() {
+            return Err(ExecutableError::OutsideMemory { addr: start, len: code.len() });
+        }
+        let ram = self.bus.ram_mut();
+        ram.fill(0);
+        ram[start..start + code.len()].copy_from_slice(code);
+        self.code = Some(start..start + code.len());
+        self.cache.clear();
+        self.registers.fill(0);
+        self.stack.clear();
+        self.flags = 0;
+        self.fuel = None;
+        self.steps = 0;
+        self.cycles = 0;
+        self.clear_history();
+        self.state = State::Running { pc: self.config.entry as usize };
+        Ok(())
+    }
+
+    /// Loads the code of `executable` like `load` and copies its data sections to their addresses.
+    /// Fails without touching the machine if a section doesn't fit in memory
+    pub fn load_executable(&mut self, executable: &Executable) -> Result<(), ExecutableError> {
+        executable.check_fits(&self.config)?;
+        self.load(&executable.code)?;
+        for (addr, bytes) in executable.data.iter() {
+            let addr = *addr as usize;
+            self.bus.ram_mut()[addr..addr + bytes.len()].copy_from_slice(bytes);
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Captures registers, memory, stack, flags and state. Mapped devices are left out
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state,
+            registers: self.registers.clone(),
+            flags: self.flags,
+            stack: self.stack.clone(),
+            steps: self.steps,
+            cycles: self.cycles,
+            memory: self.bus.ram().to_vec(),
+            code: self.code.clone(),
+        }
+    }
+
+    /// Puts the machine back into the state captured by `snapshot`, which needs the same memory size
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.memory.len() != self.bus.len() {
+            return Err(SnapshotError::MemorySizeMismatch { expected: self.bus.len(), found: snapshot.memory.len() });
+        }
+        if snapshot.registers.len() != self.registers.len() {
+            return Err(SnapshotError::Invalid("register count"));
+        }
+        if snapshot.stack.len() > STACK_SIZE {
+            return Err(SnapshotError::Invalid("stack"));
+        }
+        self.bus.ram_mut().copy_from_slice(&snapshot.memory);
+        self.cache.clear();
+        self.registers.copy_from_slice(&snapshot.registers);
+        self.stack.clone_from(&snapshot.stack);
+        self.flags = snapshot.flags;
+        self.steps = snapshot.steps;
+        self.cycles = snapshot.cycles;
+        self.state = snapshot.state;
+        self.code = snapshot.code.clone();
+        self.clear_history();
+        Ok(())
+    }
+
+    /// Keeps an undo log of the last `limit` steps so they can be stepped back, or stops keeping one with `None`.
+    /// Console i/o and mapped devices can't be undone
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.history = limit.map(|limit| (VecDeque::new(), limit));
+    }
+
+    /// Number of steps that can currently be undone
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |(log, _)| log.len())
+    }
+
+    fn clear_history(&mut self) {
+        if let Some((ref mut log, _)) = self.history {
+            log.clear();
+        }
+    }
+
+    /// Undoes the last step. Returns false if there is no history left
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history {
+            Some((ref mut log, _)) => log.pop_back(),
+            None => None,
+        };
+        let undo = match undo {
+            Some(undo) => undo,
+            None => return false,
+        };
+        for (addr, old, _) in undo.memory.iter().rev() {
+            self.bus.ram_mut()[*addr] = *old;
+            self.invalidate(*addr);
+        }
+        for (reg, old) in undo.registers.iter().rev() {
+            self.registers[*reg as usize] = *old;
+        }
+        for change in undo.stack.iter().rev() {
+            match *change {
+                StackChange::Push => {
+                    self.stack.pop();
+                },
+                StackChange::Pop(val) => self.stack.push(val),
+            }
+        }
+        self.state = undo.state;
+        self.flags = undo.flags;
+        self.steps = undo.steps;
+        self.cycles = undo.cycles;
+        self.fuel = undo.fuel;
+        true
+    }
+
+    /// The most recent write to `addr` still in the history
+    pub fn last_write(&self, addr: usize) -> Option<MemoryWrite> {
+        let (log, _) = self.history.as_ref()?;
+        log.iter().rev().find_map(|undo| {
+            let (_, old, new) = undo.memory.iter().rev().find(|(a, _, _)| *a == addr)?;
+            let pc = match undo.state {
+                State::Running { pc } => pc,
+                _ => return None,
+            };
+            Some(MemoryWrite { step: undo.steps, pc, old: *old, new: *new })
+        })
+    }
+
+    /// Limits how many more instructions may execute. Once exhausted the machine stops in `State::OutOfFuel`
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Instructions executed since the program was loaded
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Cycles spent since the program was loaded, following `cycle_cost`
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn pc(&self) -> Option<usize> {
+        match self.state {
+            State::Running { pc } => Some(pc),
+            _ => None,
+        }
+    }
+
+    /// Moves pc to `pc`, (re)starting the machine if it wasn't running
+    pub fn set_pc(&mut self, pc: usize) {
+        self.state = State::Running { pc };
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// None if the machine has no such register
+    pub fn register(&self, reg: Register) -> Option<u8> {
+        self.registers.get(reg as usize).copied()
+    }
+
+    /// Returns false if the machine has no such register
+    pub fn set_register(&mut self, reg: Register, val: u8) -> bool {
+        match self.registers.get_mut(reg as usize) {
+            Some(old) => {
+                *old = val;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.flags = flags;
+    }
+
+    /// Stack pointer, the number of bytes currently on the stack
+    pub fn sp(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn stack(&self) -> &[u8] {
+        &self.stack
+    }
+
+    /// The machine's RAM, without the devices mapped over it
+    pub fn memory(&self) -> &[u8] {
+        self.bus.ram()
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.cache.clear();
+        self.bus.ram_mut()
+    }
+
+    /// None if `addr` lies outside of memory
+    pub fn read(&self, addr: CAddress) -> Option<u8> {
+        self.bus.ram().get(addr as usize).copied()
+    }
+
+    /// Returns false if `addr` lies outside of memory
+    pub fn write(&mut self, addr: CAddress, val: u8) -> bool {
+        match self.bus.ram_mut().get_mut(addr as usize) {
+            Some(old) => *old = val,
+            None => return false,
+        }
+        self.invalidate(addr as usize);
+        true
+    }
+
+    /// Fetches the instruction at pc and moves pc past it
+    pub fn fetch(&mut self) -> Result<Op, Fault> {
+        let pc = self.pc().ok_or(Fault::NotRunning { pc: self.ins_pc })?;
+        self.ins_pc = pc;
+        if pc >= self.bus.len() {
+            return Err(Fault::PcOutOfRange { pc });
+        }
+        if self.protection.non_executable_data && matches!(self.code, Some(ref code) if !code.contains(&pc)) {
+            return Err(Fault::NotExecutable { pc });
+        }
+        let ins = match self.engine {
+            Engine::Interpreter => self.decode(pc)?,
+            Engine::Cached => match self.cache.get(pc) {
+                Some(Some(ins)) => *ins,
+                _ => {
+                    let ins = self.decode(pc)?;
+                    if self.cache.is_empty() {
+                        self.cache.resize(self.bus.len(), None);
+                    }
+                    self.cache[pc] = Some(ins);
+                    ins
+                },
+            },
+        };
+        self.state = State::Running { pc: pc + ins.get_size() };
+        Ok(ins)
+    }
+
+    /// Decodes the instruction at `addr` without executing it
+    pub fn decode(&self, addr: usize) -> Result<Op, Fault> {
+        Ok(shared::decode(self.bus.ram(), addr)?.0)
+    }
+
+    fn reg(&self, reg: Register) -> Result<u8, Fault> {
+        let val = self.registers.get(reg as usize).copied().ok_or(Fault::BadRegister { pc: self.ins_pc, reg })?;
+        if self.watching() {
+            let mut reads = self.register_reads.take();
+            reads.push((reg, val));
+            self.register_reads.set(reads);
+        }
+        Ok(val)
+    }
+
+    /// Address held by a pair of registers, the high byte in `hi`
+    fn pair(&self, hi: Register, lo: Register) -> Result<usize, Fault> {
+        Ok(address_from_bytes([self.reg(lo)?, self.reg(hi)?]) as usize)
+    }
+
+    fn reg_mut(&mut self, reg: Register) -> Result<&mut u8, Fault> {
+        if let (true, Some(old)) = (self.watching(), self.registers.get(reg as usize)) {
+            // the new value is filled in once the instruction is done
+            self.accesses.push((Target::Register(reg), Access::Write, *old, *old));
+        }
+        if let (Some(journal), Some(old)) = (self.journal.as_mut(), self.registers.get(reg as usize)) {
+            journal.registers.push((reg, *old));
+        }
+        self.registers.get_mut(reg as usize).ok_or(Fault::BadRegister { pc: self.ins_pc, reg })
+    }
+
+    fn bus_read(&mut self, addr: usize) -> Result<u8, Fault> {
+        let val = self.bus.read(addr).ok_or(Fault::AddressOutOfRange { pc: self.ins_pc, addr })?;
+        if self.watching() {
+            self.accesses.push((Target::Memory(addr), Access::Read, val, val));
+        }
+        Ok(val)
+    }
+
+    fn bus_write(&mut self, addr: usize, val: u8) -> Result<(), Fault> {
+        if !self.bus.is_mapped(addr) {
+            if self.protection.read_only_code && matches!(self.code, Some(ref code) if code.contains(&addr)) {
+                return Err(Fault::WriteProtected { pc: self.ins_pc, addr });
+            }
+            if let (Some(journal), Some(old)) = (self.journal.as_mut(), self.bus.ram().get(addr)) {
+                journal.memory.push((addr, *old, val));
+            }
+            self.invalidate(addr);
+        }
+        if self.watching() {
+            let old = self.bus.ram().get(addr).copied().unwrap_or(0);
+            self.accesses.push((Target::Memory(addr), Access::Write, old, val));
+        }
+        self.bus.write(addr, val).ok_or(Fault::AddressOutOfRange { pc: self.ins_pc, addr })
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Writes the result of an ALU operation to `dest` and updates the flags accordingly
+    fn set_result(&mut self, dest: Register, res: u8, carry: bool, overflow: bool) -> Result<(), Fault> {
+        *self.reg_mut(dest)? = res;
+        self.flags &= FLAG_INTERRUPT;
+        if res == 0 {
+            self.flags |= FLAG_ZERO;
+        }
+        if carry {
+            self.flags |= FLAG_CARRY;
+        }
+        if overflow {
+            self.flags |= FLAG_OVERFLOW;
+        }
+        if res & 0x80 != 0 {
+            self.flags |= FLAG_NEGATIVE;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, dest: Register, by: u8, carry_in: bool) -> Result<(), Fault> {
+        let val = self.reg(dest)?;
+        let (res, c1) = val.overflowing_add(by);
+        let (res, c2) = res.overflowing_add(carry_in as u8);
+        let overflow = (val ^ res) & (by ^ res) & 0x80 != 0; // both operands have a different sign than the result
+        self.set_result(dest, res, c1 || c2, overflow)
+    }
+
+    fn sub(&mut self, dest: Register, by: u8, borrow_in: bool) -> Result<(), Fault> {
+        let val = self.reg(dest)?;
+        let (res, b1) = val.overflowing_sub(by);
+        let (res, b2) = res.overflowing_sub(borrow_in as u8);
+        let overflow = (val ^ by) & (val ^ res) & 0x80 != 0;
+        self.set_result(dest, res, b1 || b2, overflow) // carry holds the borrow
+    }
+
+    fn mul(&mut self, dest: Register, by: u8) -> Result<(), Fault> {
+        let wide = self.reg(dest)? as u16 * by as u16;
+        self.set_result(dest, wide as u8, wide > 0xFF, wide > 0xFF)
+    }
+
+    fn push(&mut self, val: u8) -> Result<(), Fault> {
+        if self.stack.len() == STACK_SIZE {
+            return Err(Fault::StackOverflow { pc: self.ins_pc });
+        }
+        self.stack.push(val);
+        if let Some(ref mut journal) = self.journal {
+            journal.stack.push(StackChange::Push);
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u8, Fault> {
+        let val = self.stack.pop().ok_or(Fault::StackUnderflow { pc: self.ins_pc })?;
+        if let Some(ref mut journal) = self.journal {
+            journal.stack.push(StackChange::Pop(val));
+        }
+        Ok(val)
+    }
+
+    /// Executes `ins` as if it was fetched from the current pc
+    pub fn execute(&mut self, ins: Op) -> Result<(), Fault> {
+        match ins {
+            Op::MOVRN(dest, src) => {
+                *self.reg_mut(dest)? = src;
+            },
+            Op::MOVRR(dest, src) => {
+                *self.reg_mut(dest)? = self.reg(src)?;
+            },
+            Op::MOVRA(dest, src) => {
+                *self.reg_mut(dest)? = self.bus_read(src as usize)?;
+            },
+            Op::MOVRX(dest, src) => {
+                let src = self.reg(src)?;
+                *self.reg_mut(dest)? = self.bus_read(src as usize)?;
+            },
+
+            Op::MOVAN(dest, src) => {
+                self.bus_write(dest as usize, src)?;
+            },
+            Op::MOVAR(dest, src) => {
+                let val = self.reg(src)?;
+                self.bus_write(dest as usize, val)?;
+            },
+            Op::MOVAA(dest, src) => {
+                let val = self.bus_read(src as usize)?;
+                self.bus_write(dest as usize, val)?;
+            },
+            Op::MOVAX(dest, src) => {
+                let val = self.bus_read(self.reg(src)? as usize)?;
+                self.bus_write(dest as usize, val)?;
+            },
+
+            Op::MOVXN(dest, src) => {
+                self.bus_write(self.reg(dest)? as usize, src)?;
+            },
+            Op::MOVXR(dest, src) => {
+                let val = self.reg(src)?;
+                self.bus_write(self.reg(dest)? as usize, val)?;
+            },
+            Op::MOVXA(dest, src) => {
+                let val = self.bus_read(src as usize)?;
+                self.bus_write(self.reg(dest)? as usize, val)?;
+            },
+            Op::MOVXX(dest, src) => {
+                let val = self.bus_read(self.reg(src)? as usize)?;
+                self.bus_write(self.reg(dest)? as usize, val)?;
+            },
+
+            Op::MOVRP(dest, hi, lo) => {
+                let src = self.pair(hi, lo)?;
+                *self.reg_mut(dest)? = self.bus_read(src)?;
+            },
+            Op::MOVPN(hi, lo, src) => {
+                self.bus_write(self.pair(hi, lo)?, src)?;
+            },
+            Op::MOVPR(hi, lo, src) => {
+                let val = self.reg(src)?;
+                self.bus_write(self.pair(hi, lo)?, val)?;
+            },
+
+            Op::ADDRN(dest, by) => {
+                self.add(dest, by, false)?;
+            },
+            Op::ADDRR(dest, src) => {
+                let by = self.reg(src)?;
+                self.add(dest, by, false)?;
+            },
+            Op::ADCRN(dest, by) => {
+                self.add(dest, by, self.flag(FLAG_CARRY))?;
+            },
+            Op::ADCRR(dest, src) => {
+                let by = self.reg(src)?;
+                self.add(dest, by, self.flag(FLAG_CARRY))?;
+            },
+            Op::SUBRN(dest, by) => {
+                self.sub(dest, by, false)?;
+            },
+            Op::SUBRR(dest, src) => {
+                let by = self.reg(src)?;
+                self.sub(dest, by, false)?;
+            },
+            Op::SBBRN(dest, by) => {
+                self.sub(dest, by, self.flag(FLAG_CARRY))?;
+            },
+            Op::SBBRR(dest, src) => {
+                let by = self.reg(src)?;
+                self.sub(dest, by, self.flag(FLAG_CARRY))?;
+            },
+            Op::MULRN(dest, by) => {
+                self.mul(dest, by)?;
+            },
+            Op::MULRR(dest, src) => {
+                let by = self.reg(src)?;
+                self.mul(dest, by)?;
+            },
+            Op::DIVRN(dest, by) => {
+                let res = self.reg(dest)?.checked_div(by).ok_or(Fault::DivideByZero { pc: self.ins_pc })?;
+                self.set_result(dest, res, false, false)?;
+            },
+            Op::DIVRR(dest, src) => {
+                let res = self.reg(dest)?.checked_div(self.reg(src)?).ok_or(Fault::DivideByZero { pc: self.ins_pc })?;
+                self.set_result(dest, res, false, false)?;
+            },
+            Op::MODRN(dest, by) => {
+                let res = self.reg(dest)?.checked_rem(by).ok_or(Fault::DivideByZero { pc: self.ins_pc })?;
+                self.set_result(dest, res, false, false)?;
+            },
+            Op::MODRR(dest, src) => {
+                let res = self.reg(dest)?.checked_rem(self.reg(src)?).ok_or(Fault::DivideByZero { pc: self.ins_pc })?;
+                self.set_result(dest, res, false, false)?;
+            },
+
+            Op::ANDRR(a, b) => {
+                let res = self.reg(a)? & self.reg(b)?;
+                self.set_result(a, res, false, false)?;
+            },
+            Op::ANDRN(a, b) => {
+                let res = self.reg(a)? & b;
+                self.set_result(a, res, false, false)?;
+            },
+            Op::XORRR(a, b) => {
+                let res = self.reg(a)? ^ self.reg(b)?;
+                self.set_result(a, res, false, false)?;
+            },
+            Op::XORRN(a, b) => {
+                let res = self.reg(a)? ^ b;
+                self.set_result(a, res, false, false)?;
+            },
+            Op::ORRR(a, b) => {
+                let res = self.reg(a)? | self.reg(b)?;
+                self.set_result(a, res, false, false)?;
+            },
+            Op::ORRN(a, b) => {
+                let res = self.reg(a)? | b;
+                self.set_result(a, res, false, false)?;
+            },
+
+            Op::SHR(reg) => {
+                let val = self.reg(reg)?;
+                self.set_result(reg, val >> 1, val & 0x01 != 0, false)?; // carry is the bit shifted out
+            },
+            Op::SHL(reg) => {
+                let val = self.reg(reg)?;
+                self.set_result(reg, val << 1, val & 0x80 != 0, false)?;
+            },
+
+            Op::PRINT(reg) => {
+                let byte = self.reg(reg)?;
+                self.io.write_byte(byte).map_err(|_| Fault::IoError { pc: self.ins_pc })?;
+                if let Some(ref mut journal) = self.journal {
+                    journal.output = Some(byte);
+                }
+            },
+            Op::READ(reg) => {
+                self.registers.get(reg as usize).ok_or(Fault::BadRegister { pc: self.ins_pc, reg })?;
+                // carry signals the end of input
+                match self.io.read_byte().map_err(|_| Fault::IoError { pc: self.ins_pc })? {
+                    Some(byte) => {
+                        *self.reg_mut(reg)? = byte;
+                        self.flags &= !FLAG_CARRY;
+                    }
+                    None => {
+                        *self.reg_mut(reg)? = 0;
+                        self.flags |= FLAG_CARRY;
+                    }
+                }
+            },
+
+            Op::PUSH(reg) => {
+                let val = self.reg(reg)?;
+                self.push(val)?;
+            },
+            Op::POP(reg) => {
+                // check the register before touching the stack, without it counting as a read
+                self.registers.get(reg as usize).ok_or(Fault::BadRegister { pc: self.ins_pc, reg })?;
+                *self.reg_mut(reg)? = self.pop()?;
+            },
+
+            Op::JMP(to) => {
+                self.state = State::Running { pc: to as usize };
+            },
+
+            Op::JMPIF(case, to) => {
+                let is_true = match case {
+                    Case::EQ(a, b) => {
+                        self.reg(a)? == self.reg(b)?
+                    },
+                    Case::NEQ(a, b) => {
+                        self.reg(a)? != self.reg(b)?
+                    },
+                    Case::GRT(a, b) => {
+                        self.reg(a)? > self.reg(b)?
+                    },
+                    Case::LSR(a, b) => {
+                        self.reg(a)? < self.reg(b)?
+                    },
+                    Case::GRTEQ(a, b) => {
+                        self.reg(a)? >= self.reg(b)?
+                    },
+                    Case::LSREQ(a, b) => {
+                        self.reg(a)? <= self.reg(b)?
+                    },
+
+                    Case::ZERO => self.flag(FLAG_ZERO),
+                    Case::NZERO => !self.flag(FLAG_ZERO),
+                    Case::CARRY => self.flag(FLAG_CARRY),
+                    Case::NCARRY => !self.flag(FLAG_CARRY),
+                    Case::OVFL => self.flag(FLAG_OVERFLOW),
+                    Case::NOVFL => !self.flag(FLAG_OVERFLOW),
+                    Case::NEG => self.flag(FLAG_NEGATIVE),
+                    Case::NNEG => !self.flag(FLAG_NEGATIVE),
+                };
+                if is_true {
+                    self.state = State::Running { pc: to as usize };
+                }
+            },
+
+            Op::CALL(to) => {
+                if let State::Running { pc } = self.state {
+                    // a call ending right at the top of memory has nowhere to return to
+                    let pc = CAddress::try_from(pc).map_err(|_| Fault::PcOutOfRange { pc })?;
+                    let [lo, hi] = address_to_bytes(pc);
+                    self.push(lo)?;
+                    self.push(hi)?;
+                }
+                self.state = State::Running { pc: to as usize };
+            },
+            Op::RET => {
+                let hi = self.pop()?;
+                let lo = self.pop()?;
+                self.state = State::Running { pc: address_from_bytes([lo, hi]) as usize };
+            },
+
+            Op::HALT => {
+                self.state = State::Halted(self.reg(self.config.exit_register)?);
+            }
+
+            Op::EI => self.flags |= FLAG_INTERRUPT,
+            Op::DI => self.flags &= !FLAG_INTERRUPT,
+            Op::RETI => {
+                self.flags = self.pop()?;
+                let hi = self.pop()?;
+                let lo = self.pop()?;
+                self.state = State::Running { pc: address_from_bytes([lo, hi]) as usize };
+            },
+
+            Op::NOOP => {},
+            Op::BRK => {}, // only meaningful to a debugger
+        }
+        Ok(())
+    }
+
+    /// Fetches and executes a single instruction. Does nothing unless the machine is running
+    pub fn step(&mut self) -> State {
+        if self.history.is_none() || self.pc().is_none() {
+            return self.step_unrecorded();
+        }
+
+        let (state, flags, steps, cycles, fuel) = (self.state, self.flags, self.steps, self.cycles, self.fuel);
+        // step_traced may already be collecting the writes
+        let journaled = self.journal.is_some();
+        if !journaled {
+            self.journal = Some(Journal::default());
+        }
+        self.step_unrecorded();
+        let journal = if journaled {
+            self.journal.as_ref().map(|j| Journal {
+                registers: j.registers.clone(),
+                stack: j.stack.clone(),
+                memory: j.memory.clone(),
+                output: j.output,
+            })
+        } else {
+            self.journal.take()
+        }
+        .unwrap_or_default();
+        let undo = Undo {
+            state,
+            registers: journal.registers,
+            flags,
+            stack: journal.stack,
+            steps,
+            cycles,
+            fuel,
+            memory: journal.memory,
+        };
+
+        if let Some((ref mut log, limit)) = self.history {
+            if log.len() >= limit {
+                log.pop_front();
+            }
+            log.push_back(undo);
+        }
+        self.state
+    }
+
+    fn step_unrecorded(&mut self) -> State {
+        if let State::Running { pc } = self.state {
+            match self.fuel {
+                Some(0) => {
+                    self.state = State::OutOfFuel { pc };
+                    return self.state;
+                },
+                Some(ref mut fuel) => *fuel -= 1,
+                None => {},
+            }
+            self.steps += 1;
+            self.paused = false;
+            self.watch_hits.clear();
+            self.accesses.clear();
+
+            let result = self.fetch().and_then(|ins| {
+                self.cycles += cycle_cost(ins);
+                self.execute(ins)
+            });
+            if let Err(fault) = result {
+                self.state = State::Faulted(fault);
+            }
+            if self.watching() {
+                self.check_watchpoints();
+            }
+            self.bus.tick();
+            if self.flags & FLAG_INTERRUPT != 0 {
+                if let Some(vector) = self.bus.interrupt() {
+                    self.interrupt(vector);
+                }
+            }
+        }
+        self.state
+    }
+
+    /// Address of the interrupt vector table
+    pub fn vector_table(&self) -> usize {
+        self.bus.len().saturating_sub(VECTOR_COUNT * 2)
+    }
+
+    /// Jumps to the handler of `vector` if the machine is running with interrupts enabled, and returns whether it did.
+    /// The return address and the flags are pushed, then interrupts are disabled until `reti` pops them back.
+    /// Panics if `vector` isn't below `VECTOR_COUNT`
+    pub fn interrupt(&mut self, vector: u8) -> bool {
+        assert!((vector as usize) < VECTOR_COUNT, "interrupt vector {} out of range", vector);
+        let pc = match self.state {
+            State::Running { pc } if self.flags & FLAG_INTERRUPT != 0 => pc,
+            _ => return false,
+        };
+        let pc = match CAddress::try_from(pc) {
+            Ok(pc) => pc,
+            Err(_) => return false,
+        };
+        let entry = self.vector_table() + vector as usize * 2;
+        let handler = match self.bus.ram().get(entry..entry + 2) {
+            Some(bytes) => address_from_bytes([bytes[0], bytes[1]]) as usize,
+            None => return false,
+        };
+
+        self.ins_pc = pc as usize;
+        let [lo, hi] = address_to_bytes(pc);
+        let flags = self.flags;
+        let result = self.push(lo).and_then(|_| self.push(hi)).and_then(|_| self.push(flags));
+        match result {
+            Ok(()) => {
+                self.flags &= !FLAG_INTERRUPT;
+                self.state = State::Running { pc: handler };
+            },
+            Err(fault) => self.state = State::Faulted(fault),
+        }
+        true
+    }
+
+    /// Like `step`, but also reports what the instruction changed. Returns `None` unless the machine is running and
+    /// has the fuel to execute the instruction
+    pub fn step_traced(&mut self) -> Option<TraceRecord> {
+        let pc = self.pc()?;
+        let op = self.decode(pc).ok();
+        let registers = self.registers.clone();
+        let (flags, steps) = (self.flags, self.steps);
+
+        self.journal = Some(Journal::default());
+        self.step();
+        let journal = self.journal.take().unwrap_or_default();
+        if self.steps == steps {
+            return None;
+        }
+
+        Some(TraceRecord {
+            pc,
+            op,
+            registers: registers
+                .iter()
+                .zip(self.registers.iter())
+                .enumerate()
+                .filter(|(_, (old, new))| old != new)
+                .map(|(reg, (old, new))| (reg as Register, *old, *new))
+                .collect(),
+            flags: if flags != self.flags { Some((flags, self.flags)) } else { None },
+            memory: journal.memory,
+            output: journal.output,
+            state: self.state,
+            location: None,
+        })
+    }
+
+    /// Executes at most `n` instructions, stopping early if the machine halts, reaches the end of its code or memory
+    /// or a watchpoint pauses it
+    pub fn run_for(&mut self, n: usize) -> State {
+        for _ in 0..n {
+            if self.is_done() {
+                break;
+            }
+            self.step();
+            if self.paused {
+                break;
+            }
+        }
+        self.state
+    }
+
+    /// Executes instructions until the machine halts, reaches the end of its code or memory or a watchpoint pauses it
+    pub fn run(&mut self) -> State {
+        while !self.is_done() {
+            self.step();
+            if self.paused {
+                break;
+            }
+        }
+        self.state
+    }
+
+    /// Whether the machine stopped, by halting, faulting, running out of fuel or reaching the end of memory. Reaching
+    /// the end of the loaded code counts as the end too when data can't be executed, as the next step would fault.
+    /// Jumping beyond the end of memory isn't the end, the next step faults
+    pub fn is_done(&self) -> bool {
+        match self.state {
+            State::Running { pc } => {
+                pc == self.bus.len()
+                    || (self.protection.non_executable_data && matches!(self.code, Some(ref code) if pc == code.end))
+            },
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bus::Device;
+    use bus::{Console, IntervalTimer, Rng, Timer};
+    use io::BufferIo;
+    use profile::{Counts, HotSpot, Profile};
+    use summary::Summary;
+    use trace::TraceFormat;
+    use watch::{Condition, Trigger};
+
+    fn assemble(src: &str) -> Vec<u8> {
+        let tokens = compiler::lexer::Lexer { input: src }.lex();
+        let recipe = compiler::Parser::new(tokens).parse().unwrap();
+        let mut out = Vec::new();
+        compiler::to_bytes(recipe, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_io() {
+        let mut vm = Machine::with_io(BufferIo::new(b"hi"), 256);
+        vm.load(&assemble("
+            label as echo
+                read x
+                jmp if carry to end
+                print x
+                jmp to echo
+            label as end
+            print x
+            halt
+        ")).unwrap();
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!(vm.io().output, b"hi\0");
+        assert_eq!(vm.flags() & FLAG_CARRY, FLAG_CARRY);
+    }
+
+    #[test]
+    fn test_devices() {
+        let mut vm = Machine::with_memory_size(256);
+        vm.bus_mut().map(0xF0..0xF2, Box::new(Console::new(BufferIo::new(b"a"))));
+        vm.bus_mut().map(0xF4..0xF8, Box::new(Timer::default()));
+        vm.bus_mut().map(0xF8..0xF9, Box::new(Rng::new(7)));
+        vm.load(&assemble("
+            mov $240 to x add 1 to x mov x to $240
+            mov $240 to y mov $241 to z
+            mov $244 to a
+            mov $248 to b mov $248 to c
+            halt
+        ")).unwrap();
+
+        assert!(matches!(vm.run(), State::Halted(_)));
+        assert_eq!(&vm.registers()[1..4], &[b'b', 0, 1]);
+        assert_eq!(vm.register(4).unwrap(), 5); // instructions completed before the read
+        let mut rng = Rng::new(7);
+        assert_eq!((vm.register(5).unwrap(), vm.register(6).unwrap()), (rng.read(0), rng.read(0)));
+        assert_eq!(vm.read(0xF0).unwrap(), 0); // ram under the device is untouched
+
+        // the machine and the console device can share one console
+        let io = std::rc::Rc::new(std::cell::RefCell::new(BufferIo::new(b"ab")));
+        let mut vm = Machine::with_io(io.clone(), 256);
+        vm.bus_mut().map(0xF0..0xF2, Box::new(Console::new(io.clone())));
+        vm.load(&assemble("read x mov $240 to y print y mov x to $240 halt")).unwrap();
+        assert!(matches!(vm.run(), State::Halted(_)));
+        assert_eq!(io.borrow().output, b"ba");
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut vm = Machine::with_io(BufferIo::default(), 256);
+        vm.load(&assemble("mov 104 to x mov x to $200 add 200 to x print x halt")).unwrap();
+
+        let trace: Vec<TraceRecord> = std::iter::from_fn(|| vm.step_traced()).collect();
+        assert_eq!(trace.len(), 5);
+        assert_eq!(trace[0].registers, vec![(1, 0, 104)]);
+        assert_eq!(trace[1].memory, vec![(200, 0, 104)]);
+        assert_eq!(trace[2].flags, Some((0, FLAG_CARRY)));
+        assert_eq!(trace[3].output, Some(48));
+        assert_eq!(trace[4].op, Some(Op::HALT));
+        assert_eq!(trace[4].state, State::Halted(0));
+
+        let mut text = Vec::new();
+        trace[1].write(&mut text, TraceFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "0x0003 MOVAR(200, 1)  [0x00c8] 0x00 -> 0x68\n");
+        let mut json = Vec::new();
+        trace[0].write(&mut json, TraceFormat::JsonLines).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"pc\":0,\"op\":\"MOVRN(1, 104)\",\"registers\":[{\"reg\":\"x\",\"old\":0,\"new\":104}],\"memory\":[]}\n"
+        );
+
+        // running out of fuel executes nothing, so there is nothing to record
+        vm.load(&assemble("mov 1 to x mov 2 to y")).unwrap();
+        vm.set_fuel(Some(1));
+        assert!(vm.step_traced().is_some());
+        assert_eq!(vm.step_traced(), None);
+        assert_eq!(vm.state(), State::OutOfFuel { pc: 3 });
+    }
+
+    #[test]
+    fn test_step() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 5 to x add 3 to x halt")).unwrap();
+
+        assert_eq!(vm.step(), State::Running { pc: 3 });
+        assert_eq!(vm.register(1).unwrap(), 5);
+        assert_eq!(vm.step(), State::Running { pc: 6 });
+        assert_eq!(vm.register(1).unwrap(), 8);
+        assert_eq!(vm.step(), State::Halted(0));
+        assert_eq!(vm.step(), State::Halted(0));
+    }
+
+    #[test]
+    fn test_run_for() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("label as loop add 1 to x jmp to loop")).unwrap();
+
+        assert_eq!(vm.run_for(10), State::Running { pc: 0 });
+        assert_eq!(vm.register(1).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("label as loop add 1 to x jmp to loop")).unwrap();
+        vm.set_fuel(Some(7));
+
+        assert_eq!(vm.run(), State::OutOfFuel { pc: 3 });
+        assert_eq!(vm.steps(), 7);
+        assert_eq!(vm.register(1).unwrap(), 4);
+        assert_eq!(vm.step(), State::OutOfFuel { pc: 3 });
+
+        // a budget that is exactly enough doesn't get in the way
+        vm.load(&assemble("mov 1 to x halt")).unwrap();
+        vm.set_fuel(Some(2));
+        assert_eq!(vm.run(), State::Halted(0));
+    }
+
+    #[test]
+    fn test_cycles() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 6 to x mul x with 7 mov x to $200 halt")).unwrap();
+
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!(vm.steps(), 4);
+        assert_eq!(vm.cycles(), 1 + 4 + 3 + 1);
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 3 to c halt")).unwrap();
+        vm.run();
+
+        let summary = Summary::of(&vm);
+        assert_eq!(summary.reason(), "halted");
+        assert_eq!(summary.exit_code(), 3);
+        let mut json = Vec::new();
+        summary.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"reason\":\"halted\",\"exit_code\":3,\"registers\":[0,0,0,0,0,0,3,0],\"flags\":0,\"steps\":2,\"cycles\":2}\n"
+        );
+
+        vm.load(&assemble("div x by 0")).unwrap();
+        vm.run();
+        assert_eq!(Summary::of(&vm).exit_code(), summary::FAULT_EXIT_CODE);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut vm = Machine::with_memory_size(256);
+        vm.load(&assemble("label as loop add 1 to x push x pop y mov y to $200 jmp to loop")).unwrap();
+        vm.run_for(12);
+
+        let bytes = vm.snapshot().to_bytes();
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot, vm.snapshot());
+
+        let mut resumed = Machine::with_memory_size(256);
+        resumed.restore(&snapshot).unwrap();
+        vm.run_for(20);
+        resumed.run_for(20);
+        assert_eq!(resumed.snapshot(), vm.snapshot());
+
+        vm.load(&assemble("push x pop y pop y")).unwrap();
+        vm.run();
+        let faulted = vm.snapshot();
+        assert_eq!(Snapshot::from_bytes(&faulted.to_bytes()), Ok(faulted));
+
+        // version 1 didn't record the code, so nothing is protected after restoring one
+        let v1 = Snapshot { code: None, ..snapshot.clone() };
+        let bytes_v1 = v1.to_bytes();
+        assert_eq!(bytes_v1[4], 1);
+        assert_eq!(Snapshot::from_bytes(&bytes_v1), Ok(v1.clone()));
+        let mut resumed = Machine::with_memory_size(256);
+        resumed.restore(&v1).unwrap();
+        assert_eq!(resumed.code_range(), None);
+        let mut protected = Machine::with_memory_size(256);
+        protected.restore(&snapshot).unwrap();
+        assert!(matches!(resumed.run_for(20), State::Running { .. }));
+        protected.run_for(20);
+        assert_eq!(resumed.snapshot(), Snapshot { code: None, ..protected.snapshot() });
+
+        assert_eq!(Snapshot::from_bytes(b"VRSX"), Err(SnapshotError::BadMagic));
+        assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotError::Truncated));
+        assert_eq!(
+            Machine::new().restore(&snapshot),
+            Err(SnapshotError::MemorySizeMismatch { expected: 0x10000, found: 256 })
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut vm = Machine::with_memory_size(256);
+        vm.set_history_limit(Some(100));
+        vm.load(&assemble("mov 5 to x mov x to $200 add 1 to x push x mov x to $200 pop y halt")).unwrap();
+        vm.run();
+        assert_eq!(vm.history_len(), 7);
+
+        let write = vm.last_write(200).unwrap();
+        assert_eq!(write, MemoryWrite { step: 4, pc: 12, old: 5, new: 6 });
+
+        for _ in 0..3 {
+            assert!(vm.step_back());
+        }
+        assert_eq!(vm.state(), State::Running { pc: 12 });
+        assert_eq!(vm.read(200).unwrap(), 5);
+        assert_eq!(vm.stack(), &[6]);
+        assert_eq!(vm.steps(), 4);
+        assert_eq!(vm.last_write(200), Some(MemoryWrite { step: 1, pc: 3, old: 0, new: 5 }));
+
+        while vm.step_back() {}
+        assert_eq!(vm.state(), State::Running { pc: 0 });
+        assert_eq!(vm.registers(), &[0; 8]);
+        assert_eq!(vm.read(200).unwrap(), 0);
+        assert_eq!(vm.last_write(200), None);
+
+        // the oldest steps are dropped past the limit
+        vm.set_history_limit(Some(2));
+        vm.run();
+        assert_eq!(vm.history_len(), 2);
+
+        // calls and returns are undone through the stack changes they journaled
+        let mut vm = Machine::with_memory_size(256);
+        vm.set_history_limit(Some(100));
+        vm.load(&assemble("mov 3 to x push x call nine pop x halt label as nine mov 9 to y push y pop z ret")).unwrap();
+        let start = vm.snapshot();
+        vm.run();
+        assert_eq!(vm.state(), State::Halted(0));
+        assert_eq!(&vm.registers()[1..4], &[3, 9, 9]);
+        while vm.step_back() {}
+        assert_eq!(vm.snapshot(), start);
+    }
+
+    #[test]
+    fn test_cached_engine() {
+        // turns `add 1 to x` into `sub 5 from x` after its first run, operand first
+        let src = "mov 0 to x mov 3 to y \
+            label as loop add 1 to x mov 5 to $8 mov 11 to z mov z to $6 \
+            jmp if x < y to loop mov x to c halt";
+        let mut results = Vec::new();
+        for engine in [Engine::Interpreter, Engine::Cached].iter() {
+            let mut vm = Machine::with_memory_size(256);
+            vm.set_engine(*engine);
+            vm.set_protection(Protection::NONE);
+            vm.load(&assemble(src)).unwrap();
+            results.push((vm.run(), vm.steps(), vm.cycles(), vm.registers().to_vec()));
+        }
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0].0, State::Halted(252));
+
+        // writes from the embedder count too
+        let mut vm = Machine::with_memory_size(256);
+        vm.set_engine(Engine::Cached);
+        vm.set_protection(Protection::NONE);
+        vm.load(&assemble("label as start add 1 to x jmp to start")).unwrap();
+        vm.run_for(4);
+        assert!(vm.write(2, 10));
+        vm.run_for(2);
+        assert_eq!(vm.register(1).unwrap(), 12);
+    }
+
+    #[test]
+    fn test_config() {
+        let config = MachineConfig {
+            registers: 4,
+            memory_size: 512,
+            exit_register: 2,
+            load_address: 0x100,
+            entry: 0x103,
+        };
+        let mut vm = Machine::with_config(BufferIo::default(), config);
+        assert_eq!(vm.registers().len(), 4);
+
+        // the first instruction is skipped by the entry point
+        vm.load(&assemble("mov 9 to y mov 5 to y halt")).unwrap();
+        assert_eq!(vm.memory()[0x100], 0x0E);
+        assert_eq!(vm.run(), State::Halted(5));
+
+        vm.load(&assemble("mov 0 to n mov 1 to a")).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::BadRegister { pc: 0x103, reg: 4 }));
+    }
+
+    #[test]
+    fn test_interrupts() {
+        // the handler counts ticks in y while the main loop counts to 50 in x
+        let src = "jmp to main \
+            label as tick add 1 to y reti \
+            label as main mov 3 to $240 mov 10 to $224 mov 50 to z ei \
+            label as loop add 1 to x jmp if x < z to loop halt";
+        let run = || {
+            let mut vm = Machine::with_memory_size(256);
+            vm.bus_mut().map(0xE0..0xE0 + IntervalTimer::SIZE, Box::new(IntervalTimer::default()));
+            vm.load(&assemble(src)).unwrap();
+            assert_eq!(vm.vector_table(), 0xF0);
+            (vm.run(), vm.registers().to_vec(), vm.steps())
+        };
+        let (state, registers, steps) = run();
+        assert_eq!(state, State::Halted(0));
+        assert_eq!(registers[1], 50);
+        assert_eq!(registers[2] as u64, (steps - 3) / 10); // every 10 instructions since the timer was set
+        assert_eq!(run(), (state, registers, steps));
+
+        // flags survive the handler, and nothing is delivered while disabled
+        let mut vm = Machine::with_memory_size(256);
+        vm.load(&assemble("label as start sub 1 from x ei di halt")).unwrap();
+        assert!(vm.write(0xF2, 7));
+        vm.run_for(1);
+        assert!(!vm.interrupt(1));
+        vm.set_flags(vm.flags() | FLAG_INTERRUPT);
+        assert!(vm.interrupt(1));
+        assert_eq!(vm.state(), State::Running { pc: 7 });
+        assert_eq!(vm.flags(), FLAG_CARRY | FLAG_NEGATIVE);
+        assert_eq!(vm.stack(), &[3, 0, FLAG_CARRY | FLAG_NEGATIVE | FLAG_INTERRUPT]);
+    }
+
+    #[test]
+    fn test_protection() {
+        let mut vm = Machine::with_memory_size(256);
+        vm.load(&assemble("mov 7 to $200 mov 1 to x mov x to $4 halt")).unwrap();
+        assert_eq!(vm.code_range(), Some(0..12));
+        assert_eq!(vm.run(), State::Faulted(Fault::WriteProtected { pc: 7, addr: 4 }));
+        assert_eq!(vm.read(200).unwrap(), 7);
+
+        vm.load(&assemble("label as start jmp to start")).unwrap();
+        vm.set_pc(100);
+        assert_eq!(vm.run(), State::Faulted(Fault::NotExecutable { pc: 100 }));
+
+        // running off the end of the code ends the program like the end of memory does
+        vm.load(&assemble("mov 1 to x mov 2 to y")).unwrap();
+        assert_eq!(vm.run(), State::Running { pc: 6 });
+        assert!(vm.is_done());
+        assert_eq!(vm.registers()[1..3], [1, 2]);
+        assert!(Summary::of(&vm).end_of_memory);
+        assert_eq!(Summary::of(&vm).exit_code(), 0);
+
+        vm.set_protection(Protection { read_only_code: false, ..Protection::FULL });
+        vm.load(&assemble("mov 1 to x mov x to $4 halt")).unwrap();
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!(vm.read(4).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let src = "mov 200 to i mov 1 to x \
+            label as loop mov x to $i add 1 to i add 1 to x jmp if !zero to loop halt";
+        let mut vm = Machine::with_memory_size(256);
+        vm.load(&assemble(src)).unwrap();
+
+        // found through the indirect write of `mov x to $i`
+        let id = vm.add_watchpoint(Watchpoint { target: Target::Memory(210), trigger: Trigger::Write });
+        assert_eq!(vm.run(), State::Running { pc: 9 });
+        assert!(vm.paused());
+        assert_eq!(vm.watch_hits(), &[WatchHit {
+            id,
+            pc: 6,
+            target: Target::Memory(210),
+            access: Access::Write,
+            old: 0,
+            new: 11,
+        }]);
+        assert!(vm.remove_watchpoint(id));
+
+        let id = vm.add_watchpoint(Watchpoint { target: Target::Register(1), trigger: Trigger::Change(Condition::Greater(40)) });
+        vm.run();
+        assert_eq!(vm.register(1).unwrap(), 41);
+        assert_eq!(vm.watch_hits()[0].id, id);
+
+        // a callback can count hits without pausing
+        let reads = std::rc::Rc::new(Cell::new(0));
+        let counter = reads.clone();
+        vm.remove_watchpoint(id);
+        vm.add_watchpoint(Watchpoint { target: Target::Register(7), trigger: Trigger::Read });
+        vm.set_watch_callback(Some(Box::new(move |_| {
+            counter.set(counter.get() + 1);
+            WatchAction::Continue
+        })));
+        vm.run_for(8);
+        assert!(!vm.paused());
+        assert_eq!(reads.get(), 4); // twice per iteration, by the indirect write and the increment
+
+        // read and pop only write their register
+        let mut vm = Machine::with_io(BufferIo::new(b"a"), 256);
+        vm.load(&assemble("push y pop x read x print x halt")).unwrap();
+        vm.add_watchpoint(Watchpoint { target: Target::Register(1), trigger: Trigger::Read });
+        let reads = std::rc::Rc::new(Cell::new(0));
+        let counter = reads.clone();
+        vm.set_watch_callback(Some(Box::new(move |hit| {
+            counter.set(counter.get() + 1);
+            assert_eq!(hit.pc, 6);
+            WatchAction::Continue
+        })));
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!(reads.get(), 1);
+        assert_eq!(vm.io().output, b"a");
+    }
+
+    #[test]
+    fn test_load_executable() {
+        let config = MachineConfig { memory_size: 256, load_address: 0x10, entry: 0x10, ..MachineConfig::default() };
+        let executable = Executable {
+            config,
+            code: assemble("mov $128 to x mov 7 to $129 halt"),
+            data: vec![(128, vec![42, 0])],
+            symbols: None,
+        };
+        let mut vm = Machine::with_config(BufferIo::default(), config);
+        vm.load_executable(&executable).unwrap();
+        assert_eq!(vm.code_range(), Some(0x10..0x19));
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!(vm.registers()[1], 42);
+        assert_eq!(vm.read(129).unwrap(), 7);
+
+        let mut small = Machine::with_config(BufferIo::default(), MachineConfig { memory_size: 129, ..config });
+        assert_eq!(small.load_executable(&executable), Err(ExecutableError::OutsideMemory { addr: 128, len: 2 }));
+        assert_eq!(small.read(0x10).unwrap(), 0);
+        assert_eq!(small.load(&[0xFF; 120]), Err(ExecutableError::OutsideMemory { addr: 0x10, len: 120 }));
+        assert_eq!(small.state(), State::Null);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let src = "mov 6 to x\nlabel as again\n    div x by n # oops\nhalt\n";
+        let mut parser = compiler::Parser::new(compiler::lexer::Lexer { input: src }.lex());
+        let mut code = Vec::new();
+        compiler::to_bytes(parser.parse().unwrap(), &mut code);
+        let info = shared::debuginfo::DebugInfo::new("div.code", parser.locations.clone(), shared::symbols::Symbols::new(&parser.labels));
+        let source = source::SourceMap::with_source(info, src);
+        assert_eq!(source.info().symbols.address_of("again"), Some(3));
+
+        let mut vm = Machine::new();
+        vm.load(&code).unwrap();
+        let mut record = vm.step_traced().unwrap();
+        record.location = source.location(record.pc);
+        let mut text = Vec::new();
+        record.write(&mut text, trace::TraceFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "0x0000 MOVRN(1, 6)  x 0x00 -> 0x06  @ 1:1\n");
+
+        assert_eq!(vm.run(), State::Faulted(Fault::DivideByZero { pc: 3 }));
+        assert_eq!(source.describe(3).as_deref(), Some("div.code:3:5: div x by n # oops"));
+        assert_eq!(source.describe(6).as_deref(), Some("div.code:4:1: halt"));
+        assert_eq!(source.describe(4), None);
+
+        let unreadable = source::SourceMap::with_source(source.info().clone(), "");
+        assert_eq!(unreadable.describe(3).as_deref(), Some("div.code:3:5"));
+    }
+
+    /// Debugger output the test can read while the debugger holds on to it
+    #[derive(Clone, Default)]
+    struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedOutput {
+        /// Everything written since the last call
+        fn take(&self) -> String {
+            String::from_utf8(self.0.borrow_mut().split_off(0)).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_debugger() {
+        let src = "mov 5 to x mov 8 to y label as loop add 1 to x mov x to $200 jmp if x < y to loop halt";
+        let mut parser = compiler::Parser::new(compiler::lexer::Lexer { input: src }.lex());
+        let mut code = Vec::new();
+        compiler::to_bytes(parser.parse().unwrap(), &mut code);
+        let symbols = shared::symbols::Symbols::new(&parser.labels);
+
+        let mut vm = Machine::with_memory_size(256);
+        vm.load(&code).unwrap();
+        let out = SharedOutput::default();
+        let mut debugger = debugger::Debugger::with_output(&mut vm, symbols, out.clone());
+        // output of a command, or its error
+        let mut run = |cmd| debugger.command(cmd).map(|_| out.take());
+
+        assert_eq!(run("step 3").unwrap(), "=> 0x0009  e2 c8 00 01        MOVAR(200, 1)\n");
+        assert_eq!(run("regs").unwrap(), "n=0x00 x=0x06 y=0x08 z=0x00 a=0x00 b=0x00 c=0x00 i=0x00 \nflags=----- sp=0 pc=0x0009 <loop+3>\n");
+        assert_eq!(run("back 2").unwrap(), "=> 0x0003  0e 02 08           MOVRN(2, 8)\n");
+        assert!(run("regs").unwrap().starts_with("n=0x00 x=0x05 y=0x00 "));
+
+        assert_eq!(run("break loop").unwrap(), "breakpoint at 0x0006 <loop>\n");
+        assert!(run("c").unwrap().starts_with("breakpoint at 0x0006 <loop>\n"));
+        assert_eq!(run("delete loop").unwrap(), "");
+        assert_eq!(run("delete loop"), Err("no breakpoint at 0x0006 <loop>".to_owned()));
+        assert_eq!(run("rc").unwrap(), "reached the start of the history\n=> 0x0000  0e 01 05           MOVRN(1, 5)\n");
+
+        assert_eq!(run("watch 200 == 7").unwrap(), "watchpoint 0 on [0x00c8]\n");
+        assert_eq!(
+            run("c").unwrap(),
+            "watchpoint 0 on [0x00c8] at 0x0009 <loop+3>: 0x06 -> 0x07\n=> 0x000d  1f 02 01 02 06 00  JMPIF(LSR(1, 2), 6)\n"
+        );
+        assert_eq!(run("unwatch 0").unwrap(), "");
+        assert_eq!(run("set x 1").unwrap(), "");
+        assert_eq!(run("set pc 300"), Err("0x012c lies outside of memory".to_owned()));
+        assert_eq!(run("set q 1"), Err("unknown register 'q'".to_owned()));
+        assert_eq!(run("poke 200 0x41").unwrap(), "");
+        assert_eq!(run("mem 200 1").unwrap(), "0x00c8  41                                               A\n");
+        assert_eq!(run("dis 0 2").unwrap(), "   0x0000  0e 01 05           MOVRN(1, 5)\n   0x0003  0e 02 08           MOVRN(2, 8)\n");
+        assert!(run("dis").unwrap().starts_with("   0x0003  0e 02 08           MOVRN(2, 8)\nloop:\n   0x0006"));
+        assert_eq!(debugger.command("q"), Ok(true));
+        drop(debugger);
+
+        assert_eq!((vm.register(1), vm.read(200)), (Some(1), Some(0x41)));
+
+        // instructions run through the stepper, which may record them
+        let stepped = std::rc::Rc::new(Cell::new(0));
+        let counter = stepped.clone();
+        let mut debugger = debugger::Debugger::with_output(&mut vm, Default::default(), std::io::sink());
+        debugger.set_stepper(move |vm| {
+            counter.set(counter.get() + 1);
+            vm.step();
+        });
+        debugger.command("step 2").unwrap();
+        assert_eq!(stepped.get(), 2);
+    }
+
+    #[test]
+    fn test_profile() {
+        let src = "mov 3 to i label as outer mov 4 to x \
+            label as inner sub 1 from x jmp if !zero to inner \
+            sub 1 from i jmp if !zero to outer halt";
+        let mut parser = compiler::Parser::new(compiler::lexer::Lexer { input: src }.lex());
+        let mut code = Vec::new();
+        compiler::to_bytes(parser.parse().unwrap(), &mut code);
+        let symbols = shared::symbols::Symbols::new(&parser.labels);
+
+        let mut vm = Machine::new();
+        vm.load(&code).unwrap();
+        let mut profile = Profile::default();
+        while !vm.is_done() {
+            profile.step(&mut vm);
+        }
+
+        assert_eq!(profile.total(), Counts { executions: vm.steps(), cycles: vm.cycles() });
+        assert_eq!(profile.addresses().next(), Some((0, Counts { executions: 1, cycles: 1 })));
+        assert_eq!(profile.hot_spots(&symbols), vec![
+            HotSpot { label: Some("inner".to_owned()), counts: Counts { executions: 31, cycles: 46 } },
+            HotSpot { label: Some("outer".to_owned()), counts: Counts { executions: 3, cycles: 3 } },
+            HotSpot { label: None, counts: Counts { executions: 1, cycles: 1 } },
+        ]);
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov $100 to x mov x to $101 halt")).unwrap();
+        assert!(vm.write(100, 42));
+        assert!(vm.set_register(6, 7));
+        assert!(!vm.set_register(8, 1));
+
+        assert_eq!(vm.run(), State::Halted(7));
+        assert_eq!(vm.read(101), Some(42));
+        assert_eq!(vm.register(1), Some(42));
+        assert_eq!(vm.register(8), None);
+
+        // loading starts from a clean machine
+        vm.set_fuel(Some(1));
+        vm.load(&assemble("halt")).unwrap();
+        assert_eq!((vm.read(101), vm.register(1), vm.fuel()), (Some(0), Some(0), None));
+
+        // outside of memory or while stopped, nothing panics
+        let mut vm = Machine::with_memory_size(256);
+        assert_eq!(vm.read(300), None);
+        assert!(!vm.write(300, 1));
+        assert_eq!(vm.fetch(), Err(Fault::NotRunning { pc: 0 }));
+    }
+
+    #[test]
+    fn test_faults() {
+        let mut vm = Machine::new();
+        vm.load(&[0x0E, 0x01, 0x05, 0x42]).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::IllegalOpcode { pc: 3, opcode: 0x42 }));
+
+        let mut vm = Machine::new();
+        vm.load(&[0x1F, 0x09, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::IllegalCondition { pc: 0, code: 0x09 }));
+
+        let mut vm = Machine::new();
+        vm.load(&[0x00, 0x0E, 0x08, 0x01]).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::BadRegister { pc: 1, reg: 8 }));
+
+        let mut vm = Machine::with_memory_size(256);
+        vm.set_protection(Protection::NONE);
+        vm.load(&[0x0F, 0xFE, 0x00]).unwrap();
+        assert!(vm.write(0xFE, 0x0E));
+        assert_eq!(vm.run(), State::Faulted(Fault::PcOutOfRange { pc: 0xFE }));
+        assert_eq!(vm.step(), State::Faulted(Fault::PcOutOfRange { pc: 0xFE }));
+
+        let mut vm = Machine::with_memory_size(256);
+        vm.load(&assemble("mov 1 to $256")).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::AddressOutOfRange { pc: 0, addr: 256 }));
+
+        // only landing right at the end of memory ends the program
+        let mut vm = Machine::with_memory_size(256);
+        vm.load(&[0x0F, 0x00, 0x10]).unwrap();
+        assert!(!vm.is_done());
+        assert_eq!(vm.run(), State::Faulted(Fault::PcOutOfRange { pc: 0x1000 }));
+        vm.load(&[0x0F, 0x00, 0x01]).unwrap();
+        assert_eq!(vm.run(), State::Running { pc: 0x100 });
+        assert!(vm.is_done());
+
+        // a call in the last bytes of memory has no return address to push
+        let config = MachineConfig { load_address: 0xFFFD, entry: 0xFFFD, ..MachineConfig::default() };
+        let mut vm = Machine::with_config(BufferIo::default(), config);
+        vm.load(&[0x2F, 0x00, 0x00]).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::PcOutOfRange { pc: 0x10000 }));
+    }
+
+    #[test]
+    fn test_mul_div_mod() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("
+            mov 20 to x mul x with 13
+            mov 7 to y mul y with y
+            mov 200 to z div z by 7
+            mov 200 to a mod a by 7
+            mov 9 to b mov 2 to c div b by c
+            halt
+        ")).unwrap();
+        vm.run();
+        assert_eq!(&vm.registers()[1..6], &[4, 49, 28, 4, 4]);
+
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 1 to x mod x by n")).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::DivideByZero { pc: 3 }));
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("
+            mov 1 to x
+            call double
+            call double
+            push x
+            pop y
+            halt
+
+            label as double
+                push x
+                pop a
+                add a to x
+                ret
+        ")).unwrap();
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!(vm.register(1).unwrap(), 4);
+        assert_eq!(vm.register(2).unwrap(), 4);
+        assert_eq!(vm.sp(), 0);
+
+        let mut vm = Machine::new();
+        vm.load(&assemble("label as rec call rec")).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::StackOverflow { pc: 0 }));
+        assert_eq!(vm.sp(), STACK_SIZE);
+
+        let mut vm = Machine::new();
+        vm.load(&assemble("push x pop y ret")).unwrap();
+        assert_eq!(vm.run(), State::Faulted(Fault::StackUnderflow { pc: 4 }));
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 200 to x add 100 to x")).unwrap();
+        vm.run_for(2);
+        assert_eq!(vm.register(1).unwrap(), 44);
+        assert_eq!(vm.flags(), FLAG_CARRY);
+
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 100 to x add 100 to x")).unwrap();
+        vm.run_for(2);
+        assert_eq!(vm.flags(), FLAG_OVERFLOW | FLAG_NEGATIVE);
+
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 3 to x sub 3 from x")).unwrap();
+        vm.run_for(2);
+        assert_eq!(vm.flags(), FLAG_ZERO);
+
+        let mut vm = Machine::new();
+        vm.load(&assemble("mov 0 to x sub 1 from x")).unwrap();
+        vm.run_for(2);
+        assert_eq!(vm.flags(), FLAG_CARRY | FLAG_NEGATIVE);
+    }
+
+    #[test]
+    fn test_multi_byte_arithmetic() {
+        // 0x01F0 + 0x0320 = 0x0510 with (y, x) and (b, a) as (high, low) pairs
+        let mut vm = Machine::new();
+        vm.load(&assemble("
+            mov 240 to x mov 1 to y
+            mov 32 to a mov 3 to b
+            add a to x
+            adc b to y
+            jmp if carry to fail
+            jmp if !zero to done
+            label as fail
+            mov 1 to c
+            label as done
+            halt
+        ")).unwrap();
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!((vm.register(2).unwrap(), vm.register(1).unwrap()), (0x05, 0x10));
+
+        // 0x0510 - 0x0320 = 0x01F0
+        let mut vm = Machine::new();
+        vm.load(&assemble("
+            mov 16 to x mov 5 to y
+            sbb 32 from x
+            sbb 3 from y
+            halt
+        ")).unwrap();
+        vm.run();
+        assert_eq!((vm.register(2).unwrap(), vm.register(1).unwrap()), (0x01, 0xF0));
+    }
+
+    #[test]
+    fn test_wide_addresses() {
+        let mut vm = Machine::new();
+        let mut code = assemble("
+            mov 42 to $1000
+            mov $1000 to $40000
+            mov $40000 to x
+        ");
+        compiler::to_bytes(vec![Op::JMP(0x1234)], &mut code);
+        code.resize(0x1234, 0x00);
+        code.push(Op::HALT.get_opcode());
+        vm.set_protection(Protection::NONE); // the data lies within the padded code
+        vm.load(&code).unwrap();
+
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!(vm.read(40000).unwrap(), 42);
+        assert_eq!(vm.register(1).unwrap(), 42);
+        assert_eq!(vm.pc(), None);
+
+        // register pairs reach past the first 256 bytes, which the code fills here
+        let mut vm = Machine::new();
+        let src = format!(
+            "{} mov 18 to y mov 52 to x mov 7 to $y:x mov $y:x to a add 1 to x mov a to $y:x halt",
+            "mov 0 to n ".repeat(100)
+        );
+        vm.load(&assemble(&src)).unwrap();
+        assert_eq!(vm.run(), State::Halted(0));
+        assert_eq!((vm.read(0x1234).unwrap(), vm.read(0x1235).unwrap(), vm.register(4).unwrap()), (7, 7, 7));
+    }
+}
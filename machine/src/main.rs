@@ -1,344 +1,382 @@
-use std::vec;
-
-use shared::*;
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use machine::{
+    bus::{
+        Console, IntervalTimer, Rng, Timer, CONSOLE_ADDRESS, INTERVAL_TIMER_ADDRESS, RNG_ADDRESS, TIMER_ADDRESS,
+    },
+    debugger::Debugger,
+    io::{Io, StdIo, StreamIo},
+    profile::Profile,
+    snapshot::Snapshot,
+    source::SourceMap,
+    summary::{Summary, FAULT_EXIT_CODE},
+    trace::TraceFormat,
+    Engine, Machine, Protection, State,
+};
+use shared::{config::MachineConfig, debuginfo::DebugInfo, executable::Executable, symbols::Symbols, Op};
+use structopt::StructOpt;
 
-#[derive(Debug)]
-enum State {
-    Running {pc: usize},
-    Halted(u8), // error code
-    Null,
+#[derive(StructOpt, Debug)]
+#[structopt(after_help = "EXIT STATUS:
+    The guest's exit code when it halts, 0 when it reaches the end of its code, 70 when it faults and 124 when it
+    runs out of --max-steps. A guest can halt with 70 or 124 as well, so scripts that need to tell these apart
+    should read the \"reason\" of the --summary instead")]
+struct ClArgs {
+    #[structopt(parse(from_os_str), required_unless = "resume")]
+    input: Option<std::path::PathBuf>,
+    /// Size of the machine's memory in bytes, 65536 unless the program header says otherwise. Can't be less than the
+    /// program's profile asks for
+    #[structopt(long = "memory")]
+    memory: Option<usize>,
+    /// Number of registers, 8 unless the program header says otherwise. Can't be less than the program's profile asks
+    /// for
+    #[structopt(long = "registers")]
+    registers: Option<u8>,
+    /// Register holding the exit code on halt, 6 (c) unless the program header says otherwise. Can't differ from the
+    /// program's profile
+    #[structopt(long = "exit-register")]
+    exit_register: Option<u8>,
+    /// Address the program is loaded at. Can't differ from the program's profile, its jumps depend on it
+    #[structopt(long = "load-address")]
+    load_address: Option<u16>,
+    /// Address execution starts at, the load address by default. Can't differ from the program's profile
+    #[structopt(long = "entry")]
+    entry: Option<u16>,
+    /// File the guest program reads its input from, instead of stdin
+    #[structopt(long = "guest-input", parse(from_os_str))]
+    guest_input: Option<std::path::PathBuf>,
+    /// File the guest program prints to, instead of stdout
+    #[structopt(long = "guest-output", parse(from_os_str))]
+    guest_output: Option<std::path::PathBuf>,
+    /// Map the built-in console, timer, random number generator and interval timer devices at 0xFF00, 0xFF10, 0xFF20
+    /// and 0xFF30
+    #[structopt(long = "devices")]
+    devices: bool,
+    /// Start an interactive debugger instead of running straight away. Without it, the debugger starts once the
+    /// program executes a brk instruction
+    #[structopt(long = "debug")]
+    debug: bool,
+    /// Symbols file written by the compiler, lets the debugger use label names. Executables bring their own
+    #[structopt(long = "symbols", parse(from_os_str))]
+    symbols: Option<std::path::PathBuf>,
+    /// Debug info file written by the compiler, lets faults, traces and the debugger show source lines
+    #[structopt(long = "debug-info", parse(from_os_str))]
+    debug_info: Option<std::path::PathBuf>,
+    /// Record every executed instruction to this file
+    #[structopt(long = "trace", parse(from_os_str))]
+    trace: Option<std::path::PathBuf>,
+    /// Format of the trace, either text or jsonl
+    #[structopt(long = "trace-format", default_value = "text")]
+    trace_format: TraceFormat,
+    /// Stop the guest after this many instructions
+    #[structopt(long = "max-steps")]
+    max_steps: Option<u64>,
+    /// Only print the guest's output, no status or registers
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+    /// Write a JSON summary of the run to this file, or to stderr with `-`. Unlike the exit status, it tells a fault
+    /// apart from a guest halting with the same code
+    #[structopt(long = "summary", parse(from_os_str))]
+    summary: Option<std::path::PathBuf>,
+    /// Continue from a snapshot file instead of loading a program
+    #[structopt(long = "resume", parse(from_os_str))]
+    resume: Option<std::path::PathBuf>,
+    /// Save a snapshot after this many steps, or when a label is first reached (needs symbols, from --symbols or the executable)
+    #[structopt(long = "snapshot-at")]
+    snapshot_at: Option<String>,
+    /// Where --snapshot-at saves the snapshot
+    #[structopt(long = "snapshot-file", parse(from_os_str), default_value = "snapshot.vrs")]
+    snapshot_file: std::path::PathBuf,
+    /// Execution engine, either interpreter or cached
+    #[structopt(long = "engine", default_value = "interpreter")]
+    engine: Engine,
+    /// Let the program write over its own code, for deliberately self-modifying programs
+    #[structopt(long = "writable-code")]
+    writable_code: bool,
+    /// Let the program execute memory outside of its code
+    #[structopt(long = "executable-data")]
+    executable_data: bool,
+    /// Count executions and cycles per instruction and print the hot spots by label (needs symbols, from --symbols or the executable)
+    #[structopt(long = "profile")]
+    profile: bool,
 }
-struct Machine {
-   registers: Vec<u8>,
-   memory: Vec<u8>,
-   state: State
+
+/// When `--snapshot-at` captures the machine
+enum SnapshotPoint {
+    Step(u64),
+    Address(usize),
 }
 
-impl Machine {
-    fn new(code: Vec<u8>) -> Self {
-        let mut machine = Self {
-            registers: vec![0u8; 8],
-            memory: vec![0u8; 256],
-            state: State::Null,
-        };
-        machine.memory[..code.len()].copy_from_slice(code.as_slice());
-        machine
-    }
-    
-    fn next_byte(&mut self) -> u8 {
-        if let State::Running { ref mut pc } = self.state {
-            let val = self.memory[*pc];
-            *pc += 1;
-            val
-        } else {
-            unimplemented!()
+impl SnapshotPoint {
+    fn parse(arg: &str, symbols: &Symbols) -> Result<Self, String> {
+        match (arg.parse(), symbols.address_of(arg)) {
+            (Ok(step), _) => Ok(SnapshotPoint::Step(step)),
+            (_, Some(addr)) => Ok(SnapshotPoint::Address(addr as usize)),
+            _ => Err(format!("--snapshot-at expects a step count or a label from the symbols file, got '{}'", arg)),
         }
     }
 
-    fn fetch(&mut self) -> Option<Op> {
-        let ins_code = self.next_byte();
-
-        match ins_code {
-            0x00 => Some(Op::NOOP),
-            0xFF => Some(Op::HALT),
-
-            0x0E => { 
-                Some(Op::MOVRN(self.next_byte(), self.next_byte()))
-            },
-            0x1E => { 
-                Some(Op::MOVRR(self.next_byte(), self.next_byte()))
-            },
-            0xAE => { 
-                Some(Op::MOVRA(self.next_byte(), self.next_byte()))
-            },
-            0xBE => { 
-                Some(Op::MOVRX(self.next_byte(), self.next_byte()))
-            },
-
-            0xE1 => { 
-                Some(Op::MOVAN(self.next_byte(), self.next_byte()))
-            },
-            0xE2 => { 
-                Some(Op::MOVAR(self.next_byte(), self.next_byte()))
-            },
-            0xE3 => { 
-                Some(Op::MOVAA(self.next_byte(), self.next_byte()))
-            },
-            0xE4 => { 
-                Some(Op::MOVAX(self.next_byte(), self.next_byte()))
-            },
-
-            0xEA => { 
-                Some(Op::MOVXN(self.next_byte(), self.next_byte()))
-            },
-            0xEB => { 
-                Some(Op::MOVXR(self.next_byte(), self.next_byte()))
-            },
-            0xEC => { 
-                Some(Op::MOVXA(self.next_byte(), self.next_byte()))
-            },
-            0xED => { 
-                Some(Op::MOVXX(self.next_byte(), self.next_byte()))
-            },
-
-            0x0A => { 
-                Some(Op::ADDRN(self.next_byte(), self.next_byte()))
-            },
-            0x1A => { 
-                Some(Op::ADDRR(self.next_byte(), self.next_byte()))
-            },
-            0x0B => { 
-                Some(Op::SUBRN(self.next_byte(), self.next_byte()))
-            },
-            0x1B => { 
-                Some(Op::SUBRR(self.next_byte(), self.next_byte()))
-            },
-            0x0C => { 
-                Some(Op::MULRN(self.next_byte(), self.next_byte()))
-            },
-            0x1C => { 
-                Some(Op::MULRR(self.next_byte(), self.next_byte()))
-            },
-            0x0D => { 
-                Some(Op::DIVRN(self.next_byte(), self.next_byte()))
-            },
-            0x1D => { 
-                Some(Op::DIVRR(self.next_byte(), self.next_byte()))
-            },
-
-            0xC5 => {
-                Some(Op::ANDRR(self.next_byte(), self.next_byte()))
-            },
-            0xC6 => {
-                Some(Op::ANDRN(self.next_byte(), self.next_byte()))
-            },
-            0xD5 => {
-                Some(Op::XORRR(self.next_byte(), self.next_byte()))
-            },
-            0xD6 => {
-                Some(Op::XORRN(self.next_byte(), self.next_byte()))
-            },
-            0xE5 => {
-                Some(Op::ORRR(self.next_byte(), self.next_byte()))
-            },
-            0xE6 => {
-                Some(Op::ORRN(self.next_byte(), self.next_byte()))
-            },
-
-            0x2D => { 
-                Some(Op::SHR(self.next_byte()))
-            },
-            0x3D => { 
-                Some(Op::SHL(self.next_byte()))
-            },
+    fn reached<I: Io>(&self, vm: &Machine<I>) -> bool {
+        match *self {
+            SnapshotPoint::Step(step) => vm.steps() == step,
+            SnapshotPoint::Address(addr) => vm.pc() == Some(addr),
+        }
+    }
+}
 
-            0xA0 => {
-                Some(Op::PRINT(self.next_byte()))
-            },
+/// What --trace, --profile and --snapshot-at record while the guest runs, with the debugger or without
+struct Recorder<'a> {
+    trace: Option<BufWriter<File>>,
+    trace_format: TraceFormat,
+    source: Option<&'a SourceMap>,
+    profile: Option<Profile>,
+    snapshot_at: Option<SnapshotPoint>,
+    snapshot_file: &'a Path,
+}
 
-            0x0F => {
-                Some(Op::JMP(self.next_byte()))
-            }
-            0x1F => {
-                let case: Case = match self.next_byte() {
-                    0x00 => Case::EQ(self.next_byte(), self.next_byte()),
-                    0x01 => Case::NEQ(self.next_byte(), self.next_byte()),
-                    0x02 => Case::LSR(self.next_byte(), self.next_byte()),
-                    0x03 => Case::GRT(self.next_byte(), self.next_byte()),
-                    0x04 => Case::LSREQ(self.next_byte(), self.next_byte()),
-                    0x05 => Case::GRTEQ(self.next_byte(), self.next_byte()),
-                    _ => {
-                        unimplemented!()
-                    }
-                };
-                Some(Op::JMPIF(case, self.next_byte()))
+impl Recorder<'_> {
+    /// Executes a single instruction and records it
+    fn step<I: Io>(&mut self, vm: &mut Machine<I>) {
+        self.snapshot_if_reached(vm);
+        let (pc, cycles, steps) = (vm.pc(), vm.cycles(), vm.steps());
+        match self.trace {
+            Some(ref mut trace) => {
+                if let Some(mut record) = vm.step_traced() {
+                    record.location = self.source.and_then(|source| source.location(record.pc));
+                    record.write(trace, self.trace_format).expect("Unable to write trace");
+                }
             }
-
-            _ => {
-                None
+            None => {
+                vm.step();
             }
         }
+        // running out of fuel doesn't execute the instruction
+        if let (Some(profile), Some(pc), true) = (self.profile.as_mut(), pc, vm.steps() != steps) {
+            profile.record(pc, vm.cycles() - cycles);
+        }
     }
 
+    fn snapshot_if_reached<I: Io>(&mut self, vm: &Machine<I>) {
+        if let Some(true) = self.snapshot_at.as_ref().map(|point| point.reached(vm)) {
+            std::fs::write(self.snapshot_file, vm.snapshot().to_bytes()).expect("Unable to write snapshot file");
+            self.snapshot_at = None;
+        }
+    }
 
-    fn execute(&mut self, ins: Op) {
-        match ins {
-            Op::MOVRN(dest, src) => {
-                self.registers[dest as usize] = src as u8;
-            },
-            Op::MOVRR(dest, src) => {
-                self.registers[dest as usize] = self.registers[src as usize];
-            },
-            Op::MOVRA(dest, src) => {
-                self.registers[dest as usize] = self.memory[src as usize];
-            },
-            Op::MOVRX(dest, src) => {
-                self.registers[dest as usize] = self.memory[self.registers[src as usize] as usize];
-            },
-
-            Op::MOVAN(dest, src) => {
-                self.memory[dest as usize] = src as u8;
-            },
-            Op::MOVAR(dest, src) => {
-                self.memory[dest as usize] = self.registers[src as usize];
-            },
-            Op::MOVAA(dest, src) => {
-                self.memory[dest as usize] = self.memory[src as usize];
-            },
-            Op::MOVAX(dest, src) => {
-                self.memory[dest as usize] = self.memory[self.registers[src as usize] as usize];
-            },
-
-            Op::MOVXN(dest, src) => {
-                self.memory[self.registers[dest as usize] as usize] = src as u8;
-            },
-            Op::MOVXR(dest, src) => {
-                self.memory[self.registers[dest as usize] as usize] = self.registers[src as usize];
-            },
-            Op::MOVXA(dest, src) => {
-                self.memory[self.registers[dest as usize] as usize] = self.memory[src as usize];
-            },
-            Op::MOVXX(dest, src) => {
-                self.memory[self.registers[dest as usize] as usize] = self.memory[self.registers[src as usize] as usize];
-            },
-
-            Op::ADDRN(dest, by) => {
-                self.registers[dest as usize] = self.registers[dest as usize].wrapping_add(by);
-            },
-            Op::ADDRR(dest, src) => {
-                self.registers[dest as usize] = self.registers[dest as usize].wrapping_add(self.registers[src as usize]);
-            },
-            Op::SUBRN(dest, by) => {
-                self.registers[dest as usize] = self.registers[dest as usize].wrapping_sub(by);
-            },
-            Op::SUBRR(dest, src) => {
-                self.registers[dest as usize] = self.registers[dest as usize].wrapping_sub(self.registers[src as usize]);
-            },
-
-            Op::ANDRR(a, b) => {
-                self.registers[a as usize] &= self.registers[b as usize];
-            },
-            Op::ANDRN(a, b) => {
-                self.registers[a as usize] &= b;
-            },
-            Op::XORRR(a, b) => {
-                self.registers[a as usize] ^= self.registers[b as usize];
-            },
-            Op::XORRN(a, b) => {
-                self.registers[a as usize] ^= b;
-            },
-            Op::ORRR(a, b) => {
-                self.registers[a as usize] |= self.registers[b as usize];
-            },
-            Op::ORRN(a, b) => {
-                self.registers[a as usize] |= b;
-            },
-
-            Op::SHR(reg) => {
-                self.registers[reg as usize] >>= 1;
-            },
-            Op::SHL(reg) => {
-                self.registers[reg as usize] <<= 1;
-            },
-
-            Op::PRINT(reg) => {
-                print!("{}", self.registers[reg as usize] as char);
-            },
+    /// Writes out what was recorded once the run is over
+    fn finish<I: Io>(mut self, vm: &mut Machine<I>, symbols: &Symbols) {
+        self.snapshot_if_reached(vm);
+        if let Some(ref mut trace) = self.trace {
+            trace.flush().expect("Unable to write trace");
+        }
+        if let Some(ref profile) = self.profile {
+            vm.io_mut().flush().expect("Unable to flush guest output");
+            eprintln!();
+            profile.write_table(&mut std::io::stderr(), symbols).expect("Unable to write profile");
+        }
+    }
+}
 
-            Op::JMP(to) => {
-                match self.state {
-                    State::Running { ref mut pc } => {
-                        *pc = to as usize;
-                    },
-                    _ => {
-                        unreachable!();
-                    }
-                }
-            },
+/// Reports bad input files or flags and exits like a faulting guest would
+fn exit_with(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(FAULT_EXIT_CODE);
+}
 
-            Op::JMPIF(case, to) => {
-                match self.state {
-                    State::Running { ref mut pc } => {
-                        
-                        let is_true = match case {
-                            Case::EQ(a, b) => {
-                                self.registers[a as usize] == self.registers[b as usize]
-                            },
-                            Case::NEQ(a, b) => {
-                                self.registers[a as usize] != self.registers[b as usize]
-                            },
-                            Case::GRT(a, b) => {
-                                self.registers[a as usize] > self.registers[b as usize]
-                            },
-                            Case::LSR(a, b) => {
-                                self.registers[a as usize] < self.registers[b as usize]
-                            },
-                            Case::GRTEQ(a, b) => {
-                                self.registers[a as usize] >= self.registers[b as usize]
-                            },
-                            Case::LSREQ(a, b) => {
-                                self.registers[a as usize] <= self.registers[b as usize]
-                            },
-                        };
-                        if is_true {
-                            *pc = to as usize;
-                        }
-                    },
-                    _ => {
-                        unreachable!();
-                    }
-                }
-            },
+fn open_io(args: &ClArgs) -> Box<dyn Io> {
+    if args.guest_input.is_none() && args.guest_output.is_none() {
+        return Box::new(StdIo);
+    }
+    let input: Box<dyn Read> = match args.guest_input {
+        Some(ref path) => Box::new(BufReader::new(File::open(path).expect("Unable to open guest input file"))),
+        None => Box::new(std::io::stdin()),
+    };
+    let output: Box<dyn Write> = match args.guest_output {
+        Some(ref path) => Box::new(BufWriter::new(File::create(path).expect("Unable to create guest output file"))),
+        None => Box::new(std::io::stdout()),
+    };
+    Box::new(StreamIo { input, output })
+}
 
-            Op::HALT => {
-                self.state = State::Halted(self.registers[0x6]); // exit code is register c on halt
-            }
+fn main() {
+    let args = ClArgs::from_args();
 
-            Op::NOOP => {},
-            
-            _ => {
-                unimplemented!();
-            }
+    let snapshot = args.resume.as_ref().map(|path| {
+        let bytes = std::fs::read(path).expect("Unable to read snapshot file");
+        Snapshot::from_bytes(&bytes).unwrap_or_else(|e| exit_with(format!("Invalid snapshot file: {}", e)))
+    });
+    let program = match (&snapshot, &args.input) {
+        (None, Some(input)) => std::fs::read(input).expect("Unable to read input file"),
+        _ => Vec::new(),
+    };
+    // executables carry their own profile, raw images may start with a profile header
+    let (header, executable) = if Executable::detect(&program) {
+        match Executable::from_bytes(&program) {
+            Ok(executable) => (Some(executable.config), executable),
+            Err(err) => exit_with(format!("Invalid executable: {}", err)),
+        }
+    } else {
+        let (header, code) = MachineConfig::from_program(&program)
+            .unwrap_or_else(|e| exit_with(format!("Invalid program header: {}", e)));
+        let executable = Executable {
+            config: header.unwrap_or_default(),
+            code: code.to_vec(),
+            data: Vec::new(),
+            symbols: None,
+        };
+        (header, executable)
+    };
+
+    // executables and images with a header were compiled for their profile, flags may only make the machine bigger
+    if let Some(profile) = header {
+        let conflicts = [
+            ("--load-address", matches!(args.load_address, Some(addr) if addr != profile.load_address)),
+            ("--entry", matches!(args.entry, Some(entry) if entry != profile.entry)),
+            ("--exit-register", matches!(args.exit_register, Some(reg) if reg != profile.exit_register)),
+            ("--registers", matches!(args.registers, Some(count) if count < profile.registers)),
+            ("--memory", matches!(args.memory, Some(size) if size < profile.memory_size)),
+        ];
+        if let Some((flag, _)) = conflicts.iter().find(|(_, conflict)| *conflict) {
+            exit_with(format!("{} contradicts the profile the program was compiled for", flag));
         }
     }
 
-    fn run(&mut self) {
-        self.state = State::Running { pc: 0 };
-        loop {
-            let next = self.fetch();
+    // flags win over the program header, which wins over the defaults. Snapshots fix the memory and register count
+    let base = header.unwrap_or_default();
+    let load_address = args.load_address.unwrap_or(base.load_address);
+    let mut config = MachineConfig {
+        registers: args.registers.unwrap_or(base.registers),
+        memory_size: args.memory.unwrap_or(base.memory_size),
+        exit_register: args.exit_register.unwrap_or(base.exit_register),
+        load_address,
+        entry: args.entry.or_else(|| header.map(|h| h.entry)).unwrap_or(load_address),
+    };
+    if let Some(ref snapshot) = snapshot {
+        config.memory_size = snapshot.memory.len();
+        config.registers = snapshot.registers.len() as u8;
+    }
+    if let Err(err) = config.validate() {
+        exit_with(format!("Invalid machine profile: {}", err));
+    }
 
-            if let Some(ins) = next {
-                // println!("{:?}", ins);
-                self.execute(ins);
+    // the console device prints and reads wherever the guest does
+    let io = Rc::new(RefCell::new(open_io(&args)));
+    let mut vm = Machine::with_config(io.clone(), config);
+    vm.set_engine(args.engine);
+    vm.set_protection(Protection {
+        read_only_code: !args.writable_code,
+        non_executable_data: !args.executable_data,
+    });
+    if args.devices {
+        let bus = vm.bus_mut();
+        bus.map(CONSOLE_ADDRESS..CONSOLE_ADDRESS + Console::<StdIo>::SIZE, Box::new(Console::new(io)));
+        bus.map(TIMER_ADDRESS..TIMER_ADDRESS + Timer::SIZE, Box::new(Timer::default()));
+        bus.map(RNG_ADDRESS..RNG_ADDRESS + Rng::SIZE, Box::new(Rng::default()));
+        bus.map(INTERVAL_TIMER_ADDRESS..INTERVAL_TIMER_ADDRESS + IntervalTimer::SIZE, Box::new(IntervalTimer::default()));
+    }
+    match snapshot {
+        Some(ref snapshot) => vm.restore(snapshot).expect("Unable to restore snapshot"),
+        None => {
+            if let Err(err) = vm.load_executable(&executable) {
+                exit_with(format!("The program doesn't fit in memory: {}", err));
             }
-            if let State::Halted(error) = self.state {
-                println!("\nVM HALTED. EXIT CODE: {}", error);
+        }
+    }
+    vm.set_fuel(args.max_steps);
+
+    let source = args.debug_info.as_ref().map(|path| {
+        let text = std::fs::read_to_string(path).expect("Unable to read debug info file");
+        SourceMap::new(DebugInfo::from_text(&text).unwrap_or_else(|e| exit_with(format!("Invalid debug info file: {}", e))))
+    });
+    let symbols = match (&args.symbols, &source) {
+        (Some(path), _) => {
+            let text = std::fs::read_to_string(path).expect("Unable to read symbols file");
+            Symbols::from_text(&text).unwrap_or_else(|e| exit_with(format!("Invalid symbols file: {}", e)))
+        }
+        (None, Some(source)) => source.info().symbols.clone(),
+        (None, None) => executable.symbols.clone().unwrap_or_default(),
+    };
+
+    let mut recorder = Recorder {
+        trace: args
+            .trace
+            .as_ref()
+            .map(|path| BufWriter::new(File::create(path).expect("Unable to create trace file"))),
+        trace_format: args.trace_format,
+        source: source.as_ref(),
+        profile: if args.profile { Some(Profile::default()) } else { None },
+        snapshot_at: args
+            .snapshot_at
+            .as_ref()
+            .map(|arg| SnapshotPoint::parse(arg, &symbols).unwrap_or_else(|e| exit_with(e))),
+        snapshot_file: &args.snapshot_file,
+    };
+
+    // address of the brk instruction that stopped the run, if one did
+    let mut brk = None;
+    if !args.debug {
+        while !vm.is_done() {
+            let pc = vm.pc();
+            let at_brk = matches!(pc.map(|pc| vm.decode(pc)), Some(Ok(Op::BRK)));
+            recorder.step(&mut vm);
+            if at_brk {
+                brk = pc;
                 break;
-            } else if let State::Running { pc } = self.state {
-                if pc == self.memory.len() {
-                    println!("\nVM HALTED. REACHED EOF");
-                    break;
-                }
             }
         }
     }
-}
-
-
-
-use structopt::StructOpt;
-
-#[derive(StructOpt, Debug)]
-struct ClArgs {
-    #[structopt(parse(from_os_str))]
-    input: std::path::PathBuf,
-}
-
-fn main() {
-    let args = ClArgs::from_args();
+    let state = if args.debug || brk.is_some() {
+        let mut debugger = Debugger::new(&mut vm, symbols.clone());
+        if let Some(ref source) = source {
+            debugger.set_source(source.clone());
+        }
+        debugger.set_stepper(|vm| recorder.step(vm));
+        match brk {
+            Some(pc) => debugger.run_from_brk(pc),
+            None => debugger.run(),
+        }
+    } else {
+        vm.state()
+    };
+    recorder.finish(&mut vm, &symbols);
+    vm.io_mut().flush().expect("Unable to flush guest output");
+    if !args.quiet {
+        match state {
+            State::Halted(code) => eprintln!("\nVM HALTED. EXIT CODE: {}", code),
+            State::Faulted(fault) => match vm.decode(fault.pc()) {
+                Ok(op) => eprintln!("\nVM FAULTED: {} ({:?})", fault, op),
+                Err(_) => eprintln!("\nVM FAULTED: {}", fault),
+            },
+            State::OutOfFuel { pc } => eprintln!("\nVM OUT OF FUEL AT {:#06x}", pc),
+            State::Running { pc } if !vm.is_done() => eprintln!("\nVM STOPPED AT {:#06x}", pc),
+            _ => eprintln!("\nVM HALTED. REACHED EOF"),
+        }
+        let pc = match state {
+            State::Faulted(fault) => Some(fault.pc()),
+            State::OutOfFuel { pc } => Some(pc),
+            State::Running { pc } if !vm.is_done() => Some(pc),
+            _ => None,
+        };
+        if let Some(line) = pc.and_then(|pc| source.as_ref()?.describe(pc)) {
+            eprintln!("  at {}", line);
+        }
+        eprintln!("REGISTERS: {:?}", vm.registers());
+        eprintln!("STEPS: {} CYCLES: {}", vm.steps(), vm.cycles());
+    }
 
-    let mut vm = Machine::new(std::fs::read(args.input).expect("Unable to read input file"));
-    vm.run();
+    let summary = Summary::of(&vm);
+    match args.summary {
+        Some(ref path) if path.as_os_str() == "-" => summary.write_json(&mut std::io::stderr()),
+        Some(ref path) => File::create(path).and_then(|mut file| summary.write_json(&mut file)),
+        None => Ok(()),
+    }
+    .expect("Unable to write summary");
 
-    println!("REGISTERS: {:?}", vm.registers);
+    std::process::exit(summary.exit_code());
 }
@@ -0,0 +1,94 @@
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+use shared::{symbols::Symbols, CAddress};
+
+use crate::{io::Io, Machine, State};
+
+/// Executions and cycles spent per instruction address
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    addresses: BTreeMap<usize, Counts>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+/// A row of the hot spot table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+    /// `None` for code before the first label
+    pub label: Option<String>,
+    pub counts: Counts,
+}
+
+impl Profile {
+    /// Steps `vm` and counts the executed instruction
+    pub fn step<I: Io>(&mut self, vm: &mut Machine<I>) -> State {
+        let (pc, cycles) = (vm.pc(), vm.cycles());
+        let state = vm.step();
+        if let Some(pc) = pc {
+            self.record(pc, vm.cycles() - cycles);
+        }
+        state
+    }
+
+    /// Counts one execution of the instruction at `pc` taking `cycles`
+    pub fn record(&mut self, pc: usize, cycles: u64) {
+        let counts = self.addresses.entry(pc).or_default();
+        counts.executions += 1;
+        counts.cycles += cycles;
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = (usize, Counts)> + '_ {
+        self.addresses.iter().map(|(addr, counts)| (*addr, *counts))
+    }
+
+    pub fn total(&self) -> Counts {
+        self.addresses.values().fold(Counts::default(), |total, counts| Counts {
+            executions: total.executions + counts.executions,
+            cycles: total.cycles + counts.cycles,
+        })
+    }
+
+    /// Counts summed up by the nearest label at or before each address, most cycles first
+    pub fn hot_spots(&self, symbols: &Symbols) -> Vec<HotSpot> {
+        let mut labels: BTreeMap<Option<&str>, Counts> = BTreeMap::new();
+        for (addr, counts) in self.addresses() {
+            let label = CAddress::try_from(addr).ok().and_then(|addr| symbols.nearest(addr)).map(|(_, name)| name);
+            let total = labels.entry(label).or_default();
+            total.executions += counts.executions;
+            total.cycles += counts.cycles;
+        }
+
+        let mut spots: Vec<HotSpot> = labels
+            .into_iter()
+            .map(|(label, counts)| HotSpot { label: label.map(str::to_owned), counts })
+            .collect();
+        spots.sort_by(|a, b| b.counts.cycles.cmp(&a.counts.cycles).then(b.counts.executions.cmp(&a.counts.executions)));
+        spots
+    }
+
+    /// Writes the hot spots as a table with the share of cycles of each
+    pub fn write_table(&self, w: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        let total = self.total().cycles.max(1);
+        writeln!(w, "{:<24} {:>12} {:>12} {:>7}", "label", "executions", "cycles", "cycles%")?;
+        for spot in self.hot_spots(symbols) {
+            writeln!(
+                w,
+                "{:<24} {:>12} {:>12} {:>6.1}%",
+                spot.label.as_deref().unwrap_or("(no label)"),
+                spot.counts.executions,
+                spot.counts.cycles,
+                spot.counts.cycles as f64 * 100.0 / total as f64
+            )?;
+        }
+        Ok(())
+    }
+}
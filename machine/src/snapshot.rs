@@ -0,0 +1,193 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+    ops::Range,
+};
+
+use crate::{Fault, State};
+
+/// First bytes of every snapshot file
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"VRSS";
+/// Bumped whenever the layout below changes
+pub const SNAPSHOT_VERSION: u8 = 2;
+
+/// Everything needed to resume a machine where it left off. Mapped devices aren't part of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub state: State,
+    pub registers: Vec<u8>,
+    pub flags: u8,
+    pub stack: Vec<u8>,
+    pub steps: u64,
+    pub cycles: u64,
+    pub memory: Vec<u8>,
+    /// Where the program was loaded, so it stays protected. Version 1 didn't record it, leaving nothing protected
+    pub code: Option<Range<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    Invalid(&'static str),
+    MemorySizeMismatch { expected: usize, found: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Invalid(what) => write!(f, "snapshot has an invalid {}", what),
+            SnapshotError::MemorySizeMismatch { expected, found } => {
+                write!(f, "snapshot holds {} bytes of memory but the machine has {}", found, expected)
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    /// Layout, integers little endian: magic, version, state, flags, register count (u8) and registers,
+    /// stack length (u8) and stack, steps (u64), cycles (u64), memory length (u32) and memory, then since version 2
+    /// the start and end of the program (u32). Snapshots without a code range are written as version 1
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.memory.len() + 64);
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.push(if self.code.is_some() { SNAPSHOT_VERSION } else { 1 });
+        write_state(&mut bytes, self.state);
+        bytes.push(self.flags);
+        bytes.push(self.registers.len() as u8);
+        bytes.extend_from_slice(&self.registers);
+        bytes.push(self.stack.len() as u8);
+        bytes.extend_from_slice(&self.stack);
+        bytes.extend_from_slice(&self.steps.to_le_bytes());
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.memory);
+        if let Some(ref code) = self.code {
+            bytes.extend_from_slice(&(code.start as u32).to_le_bytes());
+            bytes.extend_from_slice(&(code.end as u32).to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.u8()?;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let state = read_state(&mut r)?;
+        let flags = r.u8()?;
+        let len = r.u8()? as usize;
+        let registers = r.take(len)?.to_vec();
+        let len = r.u8()? as usize;
+        let stack = r.take(len)?.to_vec();
+        let steps = r.u64()?;
+        let cycles = r.u64()?;
+        let len = r.u32()? as usize;
+        let memory = r.take(len)?.to_vec();
+        let code = match version {
+            1 => None,
+            _ => Some(r.u32()? as usize..r.u32()? as usize),
+        };
+        if r.pos != bytes.len() {
+            return Err(SnapshotError::Invalid("length"));
+        }
+        Ok(Self {
+            state,
+            registers,
+            flags,
+            stack,
+            steps,
+            cycles,
+            memory,
+            code,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        let bytes = self.bytes.get(self.pos..self.pos + n).ok_or(SnapshotError::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+// states and faults are a tag byte followed by a pc and an extra value, both u32
+
+fn write_state(bytes: &mut Vec<u8>, state: State) {
+    let (tag, pc, extra) = match state {
+        State::Null => (0, 0, 0),
+        State::Running { pc } => (1, pc, 0),
+        State::Halted(code) => (2, 0, code as usize),
+        State::OutOfFuel { pc } => (3, pc, 0),
+        State::Faulted(fault) => match fault {
+            Fault::IllegalOpcode { pc, opcode } => (0x10, pc, opcode as usize),
+            Fault::IllegalCondition { pc, code } => (0x11, pc, code as usize),
+            Fault::BadRegister { pc, reg } => (0x12, pc, reg as usize),
+            Fault::PcOutOfRange { pc } => (0x13, pc, 0),
+            Fault::AddressOutOfRange { pc, addr } => (0x14, pc, addr),
+            Fault::DivideByZero { pc } => (0x15, pc, 0),
+            Fault::IoError { pc } => (0x16, pc, 0),
+            Fault::StackOverflow { pc } => (0x17, pc, 0),
+            Fault::StackUnderflow { pc } => (0x18, pc, 0),
+            Fault::WriteProtected { pc, addr } => (0x19, pc, addr),
+            Fault::NotExecutable { pc } => (0x1A, pc, 0),
+            Fault::NotRunning { pc } => (0x1B, pc, 0),
+        },
+    };
+    bytes.push(tag);
+    bytes.extend_from_slice(&(pc as u32).to_le_bytes());
+    bytes.extend_from_slice(&(extra as u32).to_le_bytes());
+}
+
+fn read_state(r: &mut Reader) -> Result<State, SnapshotError> {
+    let tag = r.u8()?;
+    let pc = r.u32()? as usize;
+    let extra = r.u32()?;
+    let byte = || u8::try_from(extra).map_err(|_| SnapshotError::Invalid("state"));
+    let fault = match tag {
+        0 => return Ok(State::Null),
+        1 => return Ok(State::Running { pc }),
+        2 => return Ok(State::Halted(byte()?)),
+        3 => return Ok(State::OutOfFuel { pc }),
+        0x10 => Fault::IllegalOpcode { pc, opcode: byte()? },
+        0x11 => Fault::IllegalCondition { pc, code: byte()? },
+        0x12 => Fault::BadRegister { pc, reg: byte()? },
+        0x13 => Fault::PcOutOfRange { pc },
+        0x14 => Fault::AddressOutOfRange { pc, addr: extra as usize },
+        0x15 => Fault::DivideByZero { pc },
+        0x16 => Fault::IoError { pc },
+        0x17 => Fault::StackOverflow { pc },
+        0x18 => Fault::StackUnderflow { pc },
+        0x19 => Fault::WriteProtected { pc, addr: extra as usize },
+        0x1A => Fault::NotExecutable { pc },
+        0x1B => Fault::NotRunning { pc },
+        _ => return Err(SnapshotError::Invalid("state")),
+    };
+    Ok(State::Faulted(fault))
+}
@@ -0,0 +1,51 @@
+use std::convert::TryFrom;
+
+use shared::{
+    debuginfo::{DebugInfo, Location},
+    CAddress,
+};
+
+/// Debug info along with the lines of the source file it points to
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+    info: DebugInfo,
+    lines: Vec<String>,
+}
+
+impl SourceMap {
+    /// Reads the source file named in `info`. Locations are still known if it can't be read, just not their text
+    pub fn new(info: DebugInfo) -> Self {
+        let source = std::fs::read_to_string(&info.file).unwrap_or_default();
+        Self::with_source(info, &source)
+    }
+
+    pub fn with_source(info: DebugInfo, source: &str) -> Self {
+        Self {
+            info,
+            lines: source.lines().map(str::to_owned).collect(),
+        }
+    }
+
+    pub fn info(&self) -> &DebugInfo {
+        &self.info
+    }
+
+    /// Source location of the instruction starting at `pc`
+    pub fn location(&self, pc: usize) -> Option<Location> {
+        CAddress::try_from(pc).ok().and_then(|pc| self.info.location(pc))
+    }
+
+    /// Text of a source line, without its indentation
+    pub fn line(&self, line: usize) -> Option<&str> {
+        self.lines.get(line.checked_sub(1)?).map(|text| text.trim())
+    }
+
+    /// `examples/count.code:2:5: add 1 to x` for the instruction at `pc`
+    pub fn describe(&self, pc: usize) -> Option<String> {
+        let location = self.location(pc)?;
+        Some(match self.line(location.line) {
+            Some(text) => format!("{}:{}: {}", self.info.file, location, text),
+            None => format!("{}:{}", self.info.file, location),
+        })
+    }
+}
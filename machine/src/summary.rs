@@ -0,0 +1,68 @@
+use std::io::{self, Write};
+
+use crate::{io::Io, Machine, State};
+
+/// Exit status of the machine binary when the guest program faults. A guest may halt with it too, only the summary's
+/// state tells them apart
+pub const FAULT_EXIT_CODE: i32 = 70;
+/// Exit status when the step budget runs out, the same as `timeout`'s. Just as ambiguous as `FAULT_EXIT_CODE`
+pub const OUT_OF_FUEL_EXIT_CODE: i32 = 124;
+
+/// How a run ended, meant for scripts driving the machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub state: State,
+    /// Whether pc reached the end of memory, or of the code when data can't be executed
+    pub end_of_memory: bool,
+    pub registers: Vec<u8>,
+    pub flags: u8,
+    pub steps: u64,
+    pub cycles: u64,
+}
+
+impl Summary {
+    pub fn of<I: Io>(vm: &Machine<I>) -> Self {
+        Self {
+            state: vm.state(),
+            end_of_memory: matches!(vm.state(), State::Running { .. }) && vm.is_done(),
+            registers: vm.registers().to_vec(),
+            flags: vm.flags(),
+            steps: vm.steps(),
+            cycles: vm.cycles(),
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self.state {
+            State::Halted(_) => "halted",
+            State::Faulted(_) => "faulted",
+            State::OutOfFuel { .. } => "out_of_fuel",
+            State::Running { .. } if self.end_of_memory => "end_of_memory",
+            State::Running { .. } => "stopped",
+            State::Null => "not_loaded",
+        }
+    }
+
+    /// The guest's exit code when it halted, otherwise what the process should exit with
+    pub fn exit_code(&self) -> i32 {
+        match self.state {
+            State::Halted(code) => code as i32,
+            State::Faulted(_) => FAULT_EXIT_CODE,
+            State::OutOfFuel { .. } => OUT_OF_FUEL_EXIT_CODE,
+            _ => 0,
+        }
+    }
+
+    /// Writes the summary as a single line of JSON
+    pub fn write_json(&self, w: &mut impl Write) -> io::Result<()> {
+        write!(w, "{{\"reason\":\"{}\",\"exit_code\":{}", self.reason(), self.exit_code())?;
+        match self.state {
+            State::Faulted(fault) => write!(w, ",\"pc\":{},\"fault\":\"{}\"", fault.pc(), fault)?,
+            State::Running { pc } | State::OutOfFuel { pc } => write!(w, ",\"pc\":{}", pc)?,
+            _ => {}
+        }
+        let registers: Vec<String> = self.registers.iter().map(|r| r.to_string()).collect();
+        write!(w, ",\"registers\":[{}],\"flags\":{}", registers.join(","), self.flags)?;
+        writeln!(w, ",\"steps\":{},\"cycles\":{}}}", self.steps, self.cycles)
+    }
+}
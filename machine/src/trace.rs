@@ -0,0 +1,129 @@
+use std::io::{self, Write};
+
+use shared::{debuginfo::Location, Op, Register, REGISTER_NAMES};
+
+use crate::State;
+
+/// What a single executed instruction did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: usize,
+    /// `None` if the instruction couldn't be fetched
+    pub op: Option<Op>,
+    /// (register, old value, new value) of every register that changed
+    pub registers: Vec<(Register, u8, u8)>,
+    /// (old, new) if the flags changed
+    pub flags: Option<(u8, u8)>,
+    /// (address, old value, new value) of every write to RAM, in order
+    pub memory: Vec<(usize, u8, u8)>,
+    /// Byte printed to the console
+    pub output: Option<u8>,
+    /// State of the machine afterwards
+    pub state: State,
+    /// Source of the instruction, left for the caller to fill in from debug info
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+impl std::str::FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => Err(format!("unknown trace format '{}', expected 'text' or 'jsonl'", s)),
+        }
+    }
+}
+
+fn register_name(reg: Register) -> String {
+    match REGISTER_NAMES.get(reg as usize) {
+        Some(name) => name.to_string(),
+        None => format!("r{}", reg),
+    }
+}
+
+impl TraceRecord {
+    pub fn write(&self, w: &mut impl Write, format: TraceFormat) -> io::Result<()> {
+        match format {
+            TraceFormat::Text => self.write_text(w),
+            TraceFormat::JsonLines => self.write_json(w),
+        }
+    }
+
+    /// `0x0010 MOVXR(7, 1)  [0x00e8] 0x05 -> 0xba  @ 12:5`
+    fn write_text(&self, w: &mut impl Write) -> io::Result<()> {
+        match self.op {
+            Some(op) => write!(w, "{:#06x} {:?}", self.pc, op)?,
+            None => write!(w, "{:#06x} ???", self.pc)?,
+        }
+        for (reg, old, new) in self.registers.iter() {
+            write!(w, "  {} {:#04x} -> {:#04x}", register_name(*reg), old, new)?;
+        }
+        if let Some((old, new)) = self.flags {
+            write!(w, "  flags {:#06b} -> {:#06b}", old, new)?;
+        }
+        for (addr, old, new) in self.memory.iter() {
+            write!(w, "  [{:#06x}] {:#04x} -> {:#04x}", addr, old, new)?;
+        }
+        if let Some(byte) = self.output {
+            write!(w, "  out {:#04x}", byte)?;
+        }
+        match self.state {
+            State::Halted(code) => write!(w, "  halted {}", code)?,
+            State::Faulted(fault) => write!(w, "  faulted: {}", fault)?,
+            State::OutOfFuel { .. } => write!(w, "  out of fuel")?,
+            _ => {}
+        }
+        if let Some(location) = self.location {
+            write!(w, "  @ {}", location)?;
+        }
+        writeln!(w)
+    }
+
+    fn write_json(&self, w: &mut impl Write) -> io::Result<()> {
+        write!(w, "{{\"pc\":{}", self.pc)?;
+        match self.op {
+            Some(op) => write!(w, ",\"op\":\"{:?}\"", op)?,
+            None => write!(w, ",\"op\":null")?,
+        }
+
+        write!(w, ",\"registers\":[")?;
+        for (i, (reg, old, new)) in self.registers.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(w, "{}{{\"reg\":\"{}\",\"old\":{},\"new\":{}}}", sep, register_name(*reg), old, new)?;
+        }
+        write!(w, "]")?;
+
+        if let Some((old, new)) = self.flags {
+            write!(w, ",\"flags\":{{\"old\":{},\"new\":{}}}", old, new)?;
+        }
+
+        write!(w, ",\"memory\":[")?;
+        for (i, (addr, old, new)) in self.memory.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(w, "{}{{\"addr\":{},\"old\":{},\"new\":{}}}", sep, addr, old, new)?;
+        }
+        write!(w, "]")?;
+
+        if let Some(byte) = self.output {
+            write!(w, ",\"output\":{}", byte)?;
+        }
+        match self.state {
+            State::Halted(code) => write!(w, ",\"halted\":{}", code)?,
+            State::Faulted(fault) => write!(w, ",\"fault\":\"{}\"", fault)?,
+            State::OutOfFuel { .. } => write!(w, ",\"out_of_fuel\":true")?,
+            _ => {}
+        }
+        if let Some(location) = self.location {
+            write!(w, ",\"line\":{},\"column\":{}", location.line, location.column)?;
+        }
+        writeln!(w, "}}")
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+
+use shared::{Register, REGISTER_NAMES};
+
+/// What a watchpoint looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Memory(usize),
+    Register(Register),
+}
+
+/// Compares the value written to a watched location
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Equal(u8),
+    NotEqual(u8),
+    Less(u8),
+    Greater(u8),
+}
+
+impl Condition {
+    pub fn holds(&self, val: u8) -> bool {
+        match *self {
+            Condition::Equal(x) => val == x,
+            Condition::NotEqual(x) => val != x,
+            Condition::Less(x) => val < x,
+            Condition::Greater(x) => val > x,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Read,
+    Write,
+    /// Written with a different value for which the condition holds
+    Change(Condition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub target: Target,
+    pub trigger: Trigger,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A watchpoint that fired
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// As returned by `Machine::add_watchpoint`
+    pub id: usize,
+    /// Address of the accessing instruction
+    pub pc: usize,
+    pub target: Target,
+    pub access: Access,
+    /// Both hold the value read for reads
+    pub old: u8,
+    pub new: u8,
+}
+
+/// What the machine does after a watchpoint fired
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    Continue,
+    Pause,
+}
+
+/// Decides what happens when a watchpoint fires, see `Machine::set_watch_callback`
+pub type WatchCallback = Box<dyn FnMut(&WatchHit) -> WatchAction>;
+
+impl Watchpoint {
+    pub(crate) fn matches(&self, target: Target, access: Access, old: u8, new: u8) -> bool {
+        self.target == target
+            && match (self.trigger, access) {
+                (Trigger::Read, Access::Read) => true,
+                (Trigger::Write, Access::Write) => true,
+                (Trigger::Change(condition), Access::Write) => old != new && condition.holds(new),
+                _ => false,
+            }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Memory(addr) => write!(f, "[{:#06x}]", addr),
+            Target::Register(reg) => match REGISTER_NAMES.get(reg as usize) {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "r{}", reg),
+            },
+        }
+    }
+}
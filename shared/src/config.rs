@@ -0,0 +1,154 @@
+use std::{convert::TryInto, fmt};
+
+use crate::{CAddress, Op, Register, ADDRESS_SPACE};
+
+/// First bytes of a program that starts with a machine profile
+pub const HEADER_MAGIC: &[u8; 4] = b"VRMH";
+pub const HEADER_VERSION: u8 = 1;
+/// Magic, version, register count, exit register, memory size (u32), load address and entry
+pub const HEADER_SIZE: usize = 15;
+
+/// Shape of the machine a program runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineConfig {
+    pub registers: u8,
+    pub memory_size: usize,
+    /// Register holding the exit code when the program halts
+    pub exit_register: Register,
+    /// Where the program is copied to in memory
+    pub load_address: CAddress,
+    /// Initial pc
+    pub entry: CAddress,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            registers: 8,
+            memory_size: 0x10000,
+            exit_register: 6,
+            load_address: 0,
+            entry: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    NoRegisters,
+    BadExitRegister(Register),
+    MemoryTooLarge(usize),
+    OutsideMemory { what: &'static str, addr: usize },
+    UnsupportedVersion(u8),
+    Truncated,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::NoRegisters => write!(f, "the machine needs at least one register"),
+            ConfigError::BadExitRegister(reg) => write!(f, "exit register {} is not one of the machine's registers", reg),
+            ConfigError::MemoryTooLarge(size) => write!(f, "{} bytes of memory is more than 16-bit addresses can reach", size),
+            ConfigError::OutsideMemory { what, addr } => write!(f, "{} {:#06x} lies outside of memory", what, addr),
+            ConfigError::UnsupportedVersion(version) => write!(f, "unsupported header version {}", version),
+            ConfigError::Truncated => write!(f, "program header is truncated"),
+        }
+    }
+}
+
+impl MachineConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.registers == 0 {
+            return Err(ConfigError::NoRegisters);
+        }
+        if self.exit_register >= self.registers {
+            return Err(ConfigError::BadExitRegister(self.exit_register));
+        }
+        // the vector table sits at the top of memory, where the guest must be able to write it
+        if self.memory_size > ADDRESS_SPACE {
+            return Err(ConfigError::MemoryTooLarge(self.memory_size));
+        }
+        for (what, addr) in [("load address", self.load_address), ("entry", self.entry)].iter() {
+            if *addr as usize >= self.memory_size {
+                return Err(ConfigError::OutsideMemory { what, addr: *addr as usize });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `op` only names registers and addresses this machine has
+    pub fn check(&self, op: &Op) -> Result<(), &'static str> {
+        if op.get_registers().iter().any(|reg| *reg >= self.registers) {
+            return Err("Register is not available on this machine profile");
+        }
+        if op.get_addresses().iter().any(|addr| *addr as usize >= self.memory_size) {
+            return Err("Address lies outside of this machine profile's memory");
+        }
+        Ok(())
+    }
+
+    pub fn to_header(&self) -> [u8; HEADER_SIZE] {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(HEADER_MAGIC);
+        header[4] = HEADER_VERSION;
+        header[5] = self.registers;
+        header[6] = self.exit_register;
+        header[7..11].copy_from_slice(&(self.memory_size as u32).to_le_bytes());
+        header[11..13].copy_from_slice(&self.load_address.to_le_bytes());
+        header[13..15].copy_from_slice(&self.entry.to_le_bytes());
+        header
+    }
+
+    /// Splits a program into its profile and code. Programs without a header are returned as they are
+    pub fn from_program(program: &[u8]) -> Result<(Option<Self>, &[u8]), ConfigError> {
+        if !program.starts_with(HEADER_MAGIC) {
+            return Ok((None, program));
+        }
+        if program.len() < HEADER_SIZE {
+            return Err(ConfigError::Truncated);
+        }
+        if program[4] != HEADER_VERSION {
+            return Err(ConfigError::UnsupportedVersion(program[4]));
+        }
+        let config = Self {
+            registers: program[5],
+            exit_register: program[6],
+            memory_size: u32::from_le_bytes(program[7..11].try_into().unwrap()) as usize,
+            load_address: CAddress::from_le_bytes([program[11], program[12]]),
+            entry: CAddress::from_le_bytes([program[13], program[14]]),
+        };
+        config.validate()?;
+        Ok((Some(config), &program[HEADER_SIZE..]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let config = MachineConfig {
+            registers: 4,
+            memory_size: 512,
+            exit_register: 3,
+            load_address: 0x100,
+            entry: 0x104,
+        };
+        let mut program = config.to_header().to_vec();
+        program.extend_from_slice(&[0xFF]);
+        assert_eq!(MachineConfig::from_program(&program), Ok((Some(config), &[0xFF][..])));
+        assert_eq!(MachineConfig::from_program(&[0xFF]), Ok((None, &[0xFF][..])));
+        assert_eq!(MachineConfig::from_program(&program[..10]), Err(ConfigError::Truncated));
+
+        assert_eq!(config.check(&Op::MOVRR(3, 1)), Ok(()));
+        assert!(config.check(&Op::MOVRR(4, 1)).is_err());
+        assert!(config.check(&Op::JMP(512)).is_err());
+
+        let bad = MachineConfig { exit_register: 4, ..config };
+        assert_eq!(bad.validate(), Err(ConfigError::BadExitRegister(4)));
+        let bad = MachineConfig { memory_size: 0x10001, ..config };
+        assert_eq!(bad.validate(), Err(ConfigError::MemoryTooLarge(0x10001)));
+        assert_eq!(MachineConfig::default().validate(), Ok(()));
+    }
+}
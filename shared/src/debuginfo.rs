@@ -0,0 +1,121 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{symbols::Symbols, CAddress};
+
+/// Position in a source file, both counted from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Where every instruction of a compiled program came from, and its labels
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// Source file as the compiler was given it
+    pub file: String,
+    /// Sorted by address
+    locations: Vec<(CAddress, Location)>,
+    pub symbols: Symbols,
+}
+
+impl DebugInfo {
+    pub fn new(file: &str, mut locations: Vec<(CAddress, Location)>, symbols: Symbols) -> Self {
+        locations.sort();
+        Self { file: file.to_owned(), locations, symbols }
+    }
+
+    pub fn locations(&self) -> impl Iterator<Item = (CAddress, Location)> + '_ {
+        self.locations.iter().copied()
+    }
+
+    /// Source of the instruction starting at `addr`
+    pub fn location(&self, addr: CAddress) -> Option<Location> {
+        self.locations
+            .binary_search_by_key(&addr, |(a, _)| *a)
+            .ok()
+            .map(|i| self.locations[i].1)
+    }
+
+    /// `file <path>` first, then `line <address> <line> <column>` for every instruction and
+    /// `label <address> <name>` for every label. Addresses in hexadecimal
+    pub fn to_text(&self) -> String {
+        let mut text = format!("file {}\n", self.file);
+        for (addr, location) in self.locations() {
+            text += &format!("line {:#06x} {} {}\n", addr, location.line, location.column);
+        }
+        for (addr, name) in self.symbols.iter() {
+            text += &format!("label {:#06x} {}\n", addr, name);
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut file = None;
+        let mut locations = Vec::new();
+        let mut labels = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let err = |what: &str| format!("line {}: {}", n + 1, what);
+            let number = |s: Option<&str>| -> Result<usize, String> {
+                s.and_then(|s| s.parse().ok()).ok_or_else(|| err("expected a number"))
+            };
+            let address = |s: Option<&str>| -> Result<CAddress, String> {
+                s.and_then(|s| CAddress::from_str_radix(s.trim_start_matches("0x"), 16).ok())
+                    .ok_or_else(|| err("expected an address"))
+            };
+            let line = line.trim();
+            let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let mut words = rest.split_whitespace();
+            match kind {
+                "" => {}
+                "file" => file = Some(rest.trim().to_owned()),
+                "line" => {
+                    let addr = address(words.next())?;
+                    let location = Location { line: number(words.next())?, column: number(words.next())? };
+                    locations.push((addr, location));
+                }
+                "label" => {
+                    let addr = address(words.next())?;
+                    let name = words.next().ok_or_else(|| err("expected a label name"))?;
+                    labels.insert(name.to_owned(), addr);
+                }
+                _ => return Err(err(&format!("unknown entry '{}'", kind))),
+            }
+        }
+        let file = file.ok_or("missing the source file name")?;
+        Ok(Self::new(&file, locations, Symbols::new(&labels)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_debug_info() {
+        let labels = HashMap::from([("loop".to_owned(), 0x03)]);
+        let info = DebugInfo::new(
+            "examples/count.code",
+            vec![(0x03, Location { line: 2, column: 5 }), (0x00, Location { line: 1, column: 1 })],
+            Symbols::new(&labels),
+        );
+        assert_eq!(
+            info.to_text(),
+            "file examples/count.code\nline 0x0000 1 1\nline 0x0003 2 5\nlabel 0x0003 loop\n"
+        );
+        assert_eq!(DebugInfo::from_text(&info.to_text()), Ok(info.clone()));
+
+        assert_eq!(info.location(0x03), Some(Location { line: 2, column: 5 }));
+        assert_eq!(info.location(0x02), None);
+        assert_eq!(info.location(0x03).unwrap().to_string(), "2:5");
+
+        assert!(DebugInfo::from_text("line 0x0000 1 1\n").is_err());
+        assert_eq!(DebugInfo::from_text("file a.code\nline 0x0000 x 1\n"), Err("line 2: expected a number".to_owned()));
+    }
+}
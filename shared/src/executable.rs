@@ -0,0 +1,270 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+};
+
+use crate::{
+    config::{ConfigError, MachineConfig},
+    symbols::Symbols,
+    CAddress,
+};
+
+/// First bytes of an executable
+pub const EXECUTABLE_MAGIC: &[u8; 4] = b"VRMX";
+pub const EXECUTABLE_VERSION: u8 = 1;
+
+const SECTION_CODE: u8 = 1;
+const SECTION_DATA: u8 = 2;
+const SECTION_SYMBOLS: u8 = 3;
+
+/// Magic, version, machine profile, load address, entry and section count
+const PREAMBLE_SIZE: usize = 16;
+/// Kind, address (u16) and length (u32) of a section
+const SECTION_HEADER_SIZE: usize = 7;
+const CHECKSUM_SIZE: usize = 4;
+
+/// A program along with everything needed to run it.
+///
+/// Laid out as the magic, the format version, the machine profile (register count, exit register, memory size as u32,
+/// load address and entry), the number of sections and the sections themselves, each a kind, an address, a u32
+/// length and its bytes. A CRC-32 of everything before it closes the file. All numbers are little endian
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    /// Machine the program was compiled for, load address and entry included
+    pub config: MachineConfig,
+    /// Copied to the load address, the only memory the program may execute
+    pub code: Vec<u8>,
+    /// Initial memory contents outside of the code, by address
+    pub data: Vec<(CAddress, Vec<u8>)>,
+    pub symbols: Option<Symbols>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutableError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadChecksum { expected: u32, found: u32 },
+    Config(ConfigError),
+    UnknownSection(u8),
+    /// There's no code section, or more than one
+    BadCode,
+    InvalidSymbols(String),
+    /// A section doesn't fit in memory
+    OutsideMemory { addr: usize, len: usize },
+    /// Two sections cover the same address
+    Overlap { addr: usize },
+}
+
+impl fmt::Display for ExecutableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExecutableError::BadMagic => write!(f, "not an executable"),
+            ExecutableError::UnsupportedVersion(version) => write!(f, "unsupported executable version {}", version),
+            ExecutableError::Truncated => write!(f, "executable is truncated"),
+            ExecutableError::BadChecksum { expected, found } => {
+                write!(f, "checksum mismatch, expected {:#010x} but found {:#010x}", expected, found)
+            }
+            ExecutableError::Config(ref err) => write!(f, "{}", err),
+            ExecutableError::UnknownSection(kind) => write!(f, "unknown section kind {}", kind),
+            ExecutableError::BadCode => write!(f, "an executable needs exactly one code section"),
+            ExecutableError::InvalidSymbols(ref err) => write!(f, "invalid symbol table, {}", err),
+            ExecutableError::OutsideMemory { addr, len } => {
+                write!(f, "{} bytes at {:#06x} don't fit in memory", len, addr)
+            }
+            ExecutableError::Overlap { addr } => write!(f, "two sections overlap at {:#06x}", addr),
+        }
+    }
+}
+
+impl From<ConfigError> for ExecutableError {
+    fn from(err: ConfigError) -> Self {
+        ExecutableError::Config(err)
+    }
+}
+
+/// CRC-32 as used by zip and png
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl Executable {
+    /// Whether `program` is an executable rather than a raw image
+    pub fn detect(program: &[u8]) -> bool {
+        program.starts_with(EXECUTABLE_MAGIC)
+    }
+
+    /// Checks that every section fits in the memory of `config`, with the code at its load address, without
+    /// overlapping another
+    pub fn check_fits(&self, config: &MachineConfig) -> Result<(), ExecutableError> {
+        let mut ranges: Vec<(usize, usize)> = Some((config.load_address, &self.code))
+            .into_iter()
+            .chain(self.data.iter().map(|(addr, bytes)| (*addr, bytes)))
+            .map(|(addr, bytes)| (addr as usize, bytes.len()))
+            .collect();
+        for (addr, len) in ranges.iter() {
+            if addr + len > config.memory_size {
+                return Err(ExecutableError::OutsideMemory { addr: *addr, len: *len });
+            }
+        }
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            if pair[0].0 + pair[0].1 > pair[1].0 && pair[1].1 > 0 {
+                return Err(ExecutableError::Overlap { addr: pair[1].0 });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let symbols = self.symbols.as_ref().map(|symbols| symbols.to_text().into_bytes());
+        let sections: Vec<(u8, CAddress, &[u8])> = Some((SECTION_CODE, self.config.load_address, &self.code[..]))
+            .into_iter()
+            .chain(self.data.iter().map(|(addr, bytes)| (SECTION_DATA, *addr, &bytes[..])))
+            .chain(symbols.as_ref().map(|text| (SECTION_SYMBOLS, 0, &text[..])))
+            .collect();
+
+        let mut out = Vec::new();
+        out.extend_from_slice(EXECUTABLE_MAGIC);
+        out.push(EXECUTABLE_VERSION);
+        out.push(self.config.registers);
+        out.push(self.config.exit_register);
+        out.extend_from_slice(&(self.config.memory_size as u32).to_le_bytes());
+        out.extend_from_slice(&self.config.load_address.to_le_bytes());
+        out.extend_from_slice(&self.config.entry.to_le_bytes());
+        out.push(sections.len() as u8);
+        for (kind, addr, bytes) in sections {
+            out.push(kind);
+            out.extend_from_slice(&addr.to_le_bytes());
+            out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// Reads and validates an executable
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ExecutableError> {
+        if !Self::detect(bytes) {
+            return Err(ExecutableError::BadMagic);
+        }
+        if bytes.len() < PREAMBLE_SIZE + CHECKSUM_SIZE {
+            return Err(ExecutableError::Truncated);
+        }
+        if bytes[4] != EXECUTABLE_VERSION {
+            return Err(ExecutableError::UnsupportedVersion(bytes[4]));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+        let found = crc32(body);
+        if expected != found {
+            return Err(ExecutableError::BadChecksum { expected, found });
+        }
+
+        let config = MachineConfig {
+            registers: body[5],
+            exit_register: body[6],
+            memory_size: u32::from_le_bytes(body[7..11].try_into().unwrap()) as usize,
+            load_address: CAddress::from_le_bytes([body[11], body[12]]),
+            entry: CAddress::from_le_bytes([body[13], body[14]]),
+        };
+        config.validate()?;
+
+        let mut code = None;
+        let mut data = Vec::new();
+        let mut symbols = None;
+        let mut pos = PREAMBLE_SIZE;
+        for _ in 0..body[15] {
+            let header = body.get(pos..pos + SECTION_HEADER_SIZE).ok_or(ExecutableError::Truncated)?;
+            let addr = CAddress::from_le_bytes([header[1], header[2]]);
+            let len = u32::from_le_bytes(header[3..7].try_into().unwrap());
+            let start = pos + SECTION_HEADER_SIZE;
+            let section = usize::try_from(len)
+                .ok()
+                .and_then(|len| body.get(start..start.checked_add(len)?))
+                .ok_or(ExecutableError::Truncated)?;
+            match header[0] {
+                SECTION_CODE if code.is_none() && addr == config.load_address => code = Some(section.to_vec()),
+                SECTION_CODE => return Err(ExecutableError::BadCode),
+                SECTION_DATA => data.push((addr, section.to_vec())),
+                SECTION_SYMBOLS => {
+                    let text = std::str::from_utf8(section)
+                        .map_err(|_| ExecutableError::InvalidSymbols("not utf-8".to_owned()))?;
+                    symbols = Some(Symbols::from_text(text).map_err(ExecutableError::InvalidSymbols)?);
+                }
+                kind => return Err(ExecutableError::UnknownSection(kind)),
+            }
+            pos = start + section.len();
+        }
+        if pos != body.len() {
+            return Err(ExecutableError::Truncated);
+        }
+
+        let executable = Self {
+            config,
+            code: code.ok_or(ExecutableError::BadCode)?,
+            data,
+            symbols,
+        };
+        executable.check_fits(&config)?;
+        Ok(executable)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_executable() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let config = MachineConfig {
+            memory_size: 0x200,
+            load_address: 0x100,
+            entry: 0x104,
+            ..MachineConfig::default()
+        };
+        let labels = HashMap::from([("start".to_owned(), 0x104)]);
+        let executable = Executable {
+            config,
+            code: vec![0x0E, 0x06, 0x2A, 0xFF, 0xFF],
+            data: vec![(0x40, b"hi".to_vec())],
+            symbols: Some(Symbols::new(&labels)),
+        };
+        let bytes = executable.to_bytes();
+        assert!(Executable::detect(&bytes));
+        assert_eq!(Executable::from_bytes(&bytes), Ok(executable.clone()));
+
+        let stripped = Executable { symbols: None, ..executable.clone() };
+        assert_eq!(Executable::from_bytes(&stripped.to_bytes()), Ok(stripped));
+
+        let mut corrupt = bytes.clone();
+        corrupt[PREAMBLE_SIZE + SECTION_HEADER_SIZE] ^= 1;
+        assert!(matches!(Executable::from_bytes(&corrupt), Err(ExecutableError::BadChecksum { .. })));
+        assert_eq!(Executable::from_bytes(&bytes[..10]), Err(ExecutableError::Truncated));
+        assert_eq!(Executable::from_bytes(&[0xFF]), Err(ExecutableError::BadMagic));
+        for len in 0..bytes.len() {
+            assert!(Executable::from_bytes(&bytes[..len]).is_err());
+        }
+
+        let overlapping = Executable { data: vec![(0x102, vec![0; 4])], ..executable.clone() };
+        assert_eq!(overlapping.check_fits(&config), Err(ExecutableError::Overlap { addr: 0x102 }));
+        assert_eq!(Executable::from_bytes(&overlapping.to_bytes()), Err(ExecutableError::Overlap { addr: 0x102 }));
+        let too_big = Executable { data: vec![(0x1FF, vec![0; 2])], ..executable.clone() };
+        assert_eq!(too_big.check_fits(&config), Err(ExecutableError::OutsideMemory { addr: 0x1FF, len: 2 }));
+        let small = MachineConfig { memory_size: 0x104, ..config };
+        assert_eq!(executable.check_fits(&small), Err(ExecutableError::OutsideMemory { addr: 0x100, len: 5 }));
+        let moved = MachineConfig { load_address: 0x3E, ..config };
+        assert_eq!(executable.check_fits(&moved), Err(ExecutableError::Overlap { addr: 0x40 }));
+    }
+}
@@ -0,0 +1,506 @@
+//! The instruction set, written out once. `Op`, `Case` and their encoding, decoding, sizes and operand lists are all
+//! generated from the tables below, so adding an instruction means adding one line here
+
+use std::fmt;
+
+use crate::{address_from_bytes, address_to_bytes, CAddress, Numeral, Register, VAddress, ADDRESS_SIZE};
+
+/// Kind of an instruction operand and how it's encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// One byte register number
+    Register,
+    /// One byte immediate value
+    Numeral,
+    /// Two byte memory address, least significant byte first
+    Address,
+    /// Register holding a memory address, one byte. It only reaches the first 256 bytes of memory, a pair of
+    /// registers reaches the rest
+    VAddress,
+    /// Condition code byte followed by the operands of the condition
+    Condition,
+}
+
+impl Operand {
+    /// Encoded size. For a condition only the condition code is counted
+    pub const fn size(&self) -> usize {
+        match *self {
+            Operand::Address => ADDRESS_SIZE,
+            _ => 1,
+        }
+    }
+}
+
+/// Row of the instruction table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub name: &'static str,
+    pub opcode: u8,
+    /// Keyword of the instruction in source code
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+}
+
+impl Instruction {
+    /// Encoded size, not counting the operands of a condition
+    pub fn size(&self) -> usize {
+        1 + self.operands.iter().map(Operand::size).sum::<usize>()
+    }
+}
+
+/// Row of the condition table of `jmp if`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub name: &'static str,
+    pub code: u8,
+    /// How the condition is written in source code
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+}
+
+/// The instruction with this opcode
+pub fn instruction(opcode: u8) -> Option<&'static Instruction> {
+    INSTRUCTIONS.iter().find(|ins| ins.opcode == opcode)
+}
+
+/// The condition with this code
+pub fn condition(code: u8) -> Option<&'static Condition> {
+    CONDITIONS.iter().find(|cond| cond.code == code)
+}
+
+/// Why `decode` couldn't read an instruction. `pc` is where the instruction starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode { pc: usize, opcode: u8 },
+    UnknownCondition { pc: usize, code: u8 },
+    /// The bytes end before the instruction does
+    Truncated { pc: usize },
+}
+
+impl DecodeError {
+    pub fn pc(&self) -> usize {
+        match *self {
+            DecodeError::UnknownOpcode { pc, .. } => pc,
+            DecodeError::UnknownCondition { pc, .. } => pc,
+            DecodeError::Truncated { pc } => pc,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode {:#04x} at {:#04x}", opcode, pc),
+            DecodeError::UnknownCondition { pc, code } => write!(f, "unknown condition code {:#04x} at {:#04x}", code, pc),
+            DecodeError::Truncated { pc } => write!(f, "instruction at {:#04x} is cut short", pc),
+        }
+    }
+}
+
+/// Decodes the instruction starting at `bytes[pc]`, returning it along with its size.
+/// Never panics, whatever the bytes and `pc`
+pub fn decode(bytes: &[u8], pc: usize) -> Result<(Op, usize), DecodeError> {
+    let rest = bytes.get(pc..).unwrap_or_default();
+    let mut iter = rest.iter().copied();
+    match Op::decode(&mut iter) {
+        Some(op) => Ok((op, rest.len() - iter.len())),
+        None => Err(match *rest {
+            [opcode, ..] if instruction(opcode).is_none() => DecodeError::UnknownOpcode { pc, opcode },
+            [opcode, code, ..] if instruction(opcode).is_some_and(|ins| ins.operands.first() == Some(&Operand::Condition)) && condition(code).is_none() => {
+                DecodeError::UnknownCondition { pc, code }
+            }
+            _ => DecodeError::Truncated { pc },
+        }),
+    }
+}
+
+macro_rules! operand_type {
+    (Register) => { Register };
+    (Numeral) => { Numeral };
+    (Address) => { CAddress };
+    (VAddress) => { VAddress };
+    (Condition) => { Case };
+}
+
+macro_rules! operand_size {
+    (Condition, $val:expr) => { 1 + $val.get_size() };
+    ($kind:ident, $val:expr) => { Operand::$kind.size() };
+}
+
+macro_rules! encode_operand {
+    (Address, $val:expr, $dest:expr) => { $dest.extend_from_slice(&address_to_bytes($val)) };
+    (Condition, $val:expr, $dest:expr) => { $val.encode($dest) };
+    ($kind:ident, $val:expr, $dest:expr) => { $dest.push($val) };
+}
+
+macro_rules! decode_operand {
+    (Address, $bytes:expr) => { address_from_bytes([$bytes.next()?, $bytes.next()?]) };
+    (Condition, $bytes:expr) => { Case::decode($bytes)? };
+    ($kind:ident, $bytes:expr) => { $bytes.next()? };
+}
+
+macro_rules! operand_registers {
+    (Register, $val:expr, $dest:expr) => { $dest.push($val) };
+    (VAddress, $val:expr, $dest:expr) => { $dest.push($val) };
+    (Condition, $val:expr, $dest:expr) => { $dest.extend($val.get_registers()) };
+    ($kind:ident, $val:expr, $dest:expr) => {};
+}
+
+macro_rules! operand_addresses {
+    (Address, $val:expr, $dest:expr) => { $dest.push($val) };
+    (Condition, $val:expr, $dest:expr) => { $dest.extend($val.get_addresses()) };
+    ($kind:ident, $val:expr, $dest:expr) => {};
+}
+
+/// Generates an enum with one variant per row, its table and its encoding, decoding and size
+macro_rules! table {
+    (
+        $(#[$enum_meta:meta])*
+        enum $enum:ident, table $table:ident: $row:ident { $code:ident }
+        $(
+            $(#[$meta:meta])*
+            $name:ident $(($($field:ident: $kind:ident),*))? = $value:literal, $mnemonic:literal;
+        )*
+    ) => {
+        $(#[$enum_meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $enum {
+            $(
+                $(#[$meta])*
+                $name $(($(operand_type!($kind)),*))?,
+            )*
+        }
+
+        pub const $table: &[$row] = &[
+            $($row {
+                name: stringify!($name),
+                $code: $value,
+                mnemonic: $mnemonic,
+                operands: &[$($(Operand::$kind),*)?],
+            },)*
+        ];
+
+        impl $enum {
+            pub fn get_opcode(&self) -> u8 {
+                match *self {
+                    $($enum::$name { .. } => $value,)*
+                }
+            }
+
+            /// Row of the table describing this variant
+            pub fn row(&self) -> &'static $row {
+                let value = self.get_opcode();
+                $table.iter().find(|row| row.$code == value).expect("every variant has a row")
+            }
+
+            #[allow(unused_variables)]
+            fn operands_size(&self) -> usize {
+                match *self {
+                    $($enum::$name $(($($field),*))? => 0 $($(+ operand_size!($kind, $field))*)?,)*
+                }
+            }
+
+            /// Registers the value names directly, not the ones it dereferences
+            #[allow(unused_variables)]
+            pub fn get_registers(&self) -> Vec<Register> {
+                let mut registers = Vec::new();
+                match *self {
+                    $($enum::$name $(($($field),*))? => { $($(operand_registers!($kind, $field, registers);)*)? },)*
+                }
+                registers
+            }
+
+            /// Memory addresses encoded in the value, jump targets included
+            #[allow(unused_variables, unused_mut)]
+            pub fn get_addresses(&self) -> Vec<CAddress> {
+                let mut addresses = Vec::new();
+                match *self {
+                    $($enum::$name $(($($field),*))? => { $($(operand_addresses!($kind, $field, addresses);)*)? },)*
+                }
+                addresses
+            }
+
+            /// Appends the encoding to `dest`
+            pub fn encode(&self, dest: &mut Vec<u8>) {
+                match *self {
+                    $($enum::$name $(($($field),*))? => {
+                        dest.push($value);
+                        $($(encode_operand!($kind, $field, dest);)*)?
+                    },)*
+                }
+            }
+
+            /// Reads one encoded value off `bytes`. `None` if it's unknown or cut short
+            pub fn decode(bytes: &mut impl Iterator<Item = u8>) -> Option<Self> {
+                Some(match bytes.next()? {
+                    $($value => $enum::$name $(($(decode_operand!($kind, bytes)),*))?,)*
+                    _ => return None,
+                })
+            }
+        }
+    };
+}
+
+impl Case {
+    /// Size of the operands following the condition code
+    pub fn get_size(&self) -> usize {
+        self.operands_size()
+    }
+}
+
+impl Op {
+    pub fn get_size(&self) -> usize {
+        1 + self.operands_size()
+    }
+}
+
+table! {
+    /// Condition of a `jmp if`
+    enum Case, table CONDITIONS: Condition { code }
+
+    EQ(a: Register, b: Register) = 0x00, "==";
+    NEQ(a: Register, b: Register) = 0x01, "!=";
+    LSR(a: Register, b: Register) = 0x02, "<";
+    GRT(a: Register, b: Register) = 0x03, ">";
+    LSREQ(a: Register, b: Register) = 0x04, "<=";
+    GRTEQ(a: Register, b: Register) = 0x05, ">=";
+
+    ZERO = 0x10, "zero";
+    NZERO = 0x11, "!zero";
+    CARRY = 0x12, "carry";
+    NCARRY = 0x13, "!carry";
+    OVFL = 0x14, "overflow";
+    NOVFL = 0x15, "!overflow";
+    NEG = 0x16, "negative";
+    NNEG = 0x17, "!negative";
+}
+
+table! {
+    enum Op, table INSTRUCTIONS: Instruction { opcode }
+
+    HALT = 0xFF, "halt";
+    NOOP = 0x00, "noop";
+    BRK = 0xFE, "brk";
+
+    MOVRN(dst: Register, val: Numeral) = 0x0E, "mov";
+    MOVRR(dst: Register, src: Register) = 0x1E, "mov";
+    MOVRA(dst: Register, src: Address) = 0xAE, "mov";
+    MOVRX(dst: Register, src: VAddress) = 0xBE, "mov";
+
+    MOVAN(dst: Address, val: Numeral) = 0xE1, "mov";
+    MOVAR(dst: Address, src: Register) = 0xE2, "mov";
+    MOVAA(dst: Address, src: Address) = 0xE3, "mov";
+    MOVAX(dst: Address, src: VAddress) = 0xE4, "mov";
+
+    MOVXN(dst: VAddress, val: Numeral) = 0xEA, "mov";
+    MOVXR(dst: VAddress, src: Register) = 0xEB, "mov";
+    MOVXA(dst: VAddress, src: Address) = 0xEC, "mov";
+    MOVXX(dst: VAddress, src: VAddress) = 0xED, "mov";
+
+    /// Loads from the address held by a pair of registers, high byte first
+    MOVRP(dst: Register, hi: Register, lo: Register) = 0xBF, "mov";
+    /// Stores to the address held by a pair of registers, high byte first
+    MOVPN(hi: Register, lo: Register, val: Numeral) = 0xEE, "mov";
+    MOVPR(hi: Register, lo: Register, src: Register) = 0xEF, "mov";
+
+    ADDRN(dst: Register, val: Numeral) = 0x0A, "add";
+    ADDRR(dst: Register, src: Register) = 0x1A, "add";
+    ADCRN(dst: Register, val: Numeral) = 0x2A, "adc";
+    ADCRR(dst: Register, src: Register) = 0x3A, "adc";
+    SUBRN(dst: Register, val: Numeral) = 0x0B, "sub";
+    SUBRR(dst: Register, src: Register) = 0x1B, "sub";
+    SBBRN(dst: Register, val: Numeral) = 0x2B, "sbb";
+    SBBRR(dst: Register, src: Register) = 0x3B, "sbb";
+    MULRN(dst: Register, val: Numeral) = 0x0C, "mul";
+    MULRR(dst: Register, src: Register) = 0x1C, "mul";
+    DIVRN(dst: Register, val: Numeral) = 0x0D, "div";
+    DIVRR(dst: Register, src: Register) = 0x1D, "div";
+    MODRN(dst: Register, val: Numeral) = 0x09, "mod";
+    MODRR(dst: Register, src: Register) = 0x19, "mod";
+
+    ANDRR(dst: Register, src: Register) = 0xC5, "and";
+    ANDRN(dst: Register, val: Numeral) = 0xC6, "and";
+    XORRR(dst: Register, src: Register) = 0xD5, "xor";
+    XORRN(dst: Register, val: Numeral) = 0xD6, "xor";
+    ORRR(dst: Register, src: Register) = 0xE5, "or";
+    ORRN(dst: Register, val: Numeral) = 0xE6, "or";
+
+    SHR(reg: Register) = 0x2D, "shr";
+    SHL(reg: Register) = 0x3D, "shl";
+
+    PRINT(reg: Register) = 0xA0, "print";
+    READ(reg: Register) = 0xA1, "read";
+
+    PUSH(reg: Register) = 0x4E, "push";
+    POP(reg: Register) = 0x5E, "pop";
+
+    JMP(to: Address) = 0x0F, "jmp";
+    JMPIF(case: Condition, to: Address) = 0x1F, "jmp if";
+    CALL(to: Address) = 0x2F, "call";
+    RET = 0x3F, "ret";
+
+    /// Enables interrupts
+    EI = 0xF1, "ei";
+    /// Disables interrupts
+    DI = 0xF0, "di";
+    /// Returns from an interrupt handler
+    RETI = 0x4F, "reti";
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Deterministic operand bytes, so every run checks the same instructions
+    struct XorShift(u32);
+
+    impl Iterator for XorShift {
+        type Item = u8;
+
+        fn next(&mut self) -> Option<u8> {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            Some((self.0 >> 24) as u8)
+        }
+    }
+
+    /// Every instruction, with each condition for `jmp if`, and random operands
+    fn samples() -> Vec<Op> {
+        let mut rng = XorShift(0x9E3779B9);
+        let mut ops = Vec::new();
+        for ins in INSTRUCTIONS {
+            for cond in CONDITIONS {
+                for _ in 0..16 {
+                    let mut bytes = vec![ins.opcode];
+                    for operand in ins.operands {
+                        if let Operand::Condition = operand {
+                            bytes.push(cond.code);
+                            bytes.extend(rng.by_ref().take(cond.operands.len()));
+                        } else {
+                            bytes.extend(rng.by_ref().take(operand.size()));
+                        }
+                    }
+                    ops.push(Op::decode(&mut bytes.into_iter()).unwrap());
+                }
+            }
+        }
+        ops
+    }
+
+    #[test]
+    fn test_tables() {
+        for (i, ins) in INSTRUCTIONS.iter().enumerate() {
+            assert!(INSTRUCTIONS[..i].iter().all(|other| other.opcode != ins.opcode), "{} reuses an opcode", ins.name);
+        }
+        for (i, cond) in CONDITIONS.iter().enumerate() {
+            assert!(CONDITIONS[..i].iter().all(|other| other.code != cond.code), "{} reuses a code", cond.name);
+        }
+        assert_eq!(instruction(0x1F).map(|ins| ins.name), Some("JMPIF"));
+        assert_eq!(instruction(0x1F).map(Instruction::size), Some(4));
+        assert_eq!(condition(0x17).map(|cond| cond.mnemonic), Some("!negative"));
+        assert_eq!(instruction(0x42), None);
+        assert_eq!(condition(0x06), None);
+    }
+
+    #[test]
+    fn test_operands() {
+        assert_eq!(Op::MOVAX(0x1234, 3).get_registers(), [3]);
+        assert_eq!(Op::MOVAX(0x1234, 3).get_addresses(), [0x1234]);
+        assert_eq!(Op::MOVAA(1, 2).get_addresses(), [1, 2]);
+        assert_eq!(Op::JMPIF(Case::LSR(1, 2), 0x10).get_registers(), [1, 2]);
+        assert_eq!(Op::JMPIF(Case::ZERO, 0x10).get_registers(), []);
+        assert_eq!(Op::JMPIF(Case::ZERO, 0x10).get_addresses(), [0x10]);
+
+        // every register and address operand of the tables is reported
+        let count = |operands: &[Operand], kinds: &[Operand]| operands.iter().filter(|o| kinds.contains(o)).count();
+        for op in samples() {
+            let (registers, addresses) = match op {
+                Op::JMPIF(case, _) => (case.row().operands.len(), 1),
+                _ => (
+                    count(op.row().operands, &[Operand::Register, Operand::VAddress]),
+                    count(op.row().operands, &[Operand::Address]),
+                ),
+            };
+            assert_eq!(op.get_registers().len(), registers, "{:?}", op);
+            assert_eq!(op.get_addresses().len(), addresses, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let ops = samples();
+        for op in ops.iter() {
+            let mut bytes = Vec::new();
+            op.encode(&mut bytes);
+            assert_eq!(bytes.len(), op.get_size(), "{:?}", op);
+            assert_eq!(bytes[0], op.row().opcode, "{:?}", op);
+            assert_eq!(Op::decode(&mut bytes.iter().copied()), Some(*op));
+        }
+        for ins in INSTRUCTIONS {
+            assert!(ops.iter().any(|op| op.row() == ins), "{} wasn't checked", ins.name);
+        }
+        for cond in CONDITIONS {
+            let mut bytes = Vec::new();
+            let case = Case::decode(&mut std::iter::once(cond.code).chain(XorShift(1))).unwrap();
+            case.encode(&mut bytes);
+            assert_eq!(bytes.len(), 1 + case.get_size());
+            assert_eq!(Case::decode(&mut bytes.into_iter()), Some(case));
+            assert!(ops.iter().any(|op| matches!(op, Op::JMPIF(c, _) if c.row() == cond)), "{} wasn't checked", cond.name);
+        }
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut bytes = Vec::new();
+        Op::MOVAN(0x1234, 7).encode(&mut bytes);
+        Op::JMPIF(Case::GRT(3, 1), 0x0190).encode(&mut bytes);
+        Op::JMPIF(Case::ZERO, 2).encode(&mut bytes);
+        Op::RETI.encode(&mut bytes);
+        assert_eq!(bytes, [0xE1, 0x34, 0x12, 7, 0x1F, 0x03, 3, 1, 0x90, 0x01, 0x1F, 0x10, 2, 0, 0x4F]);
+
+        // cut short or unknown
+        assert_eq!(Op::decode(&mut bytes[..3].iter().copied()), None);
+        assert_eq!(Op::decode(&mut [0x1F, 0x20, 0, 0].iter().copied()), None);
+        assert_eq!(Op::decode(&mut [0x42].iter().copied()), None);
+        assert_eq!(Op::decode(&mut std::iter::empty()), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let bytes = [0xE1, 0x34, 0x12, 7, 0x1F, 0x10, 2, 0, 0x4F, 0x42, 0x1F, 0x09, 0, 0, 0x1F, 0x00, 1];
+        assert_eq!(decode(&bytes, 0), Ok((Op::MOVAN(0x1234, 7), 4)));
+        assert_eq!(decode(&bytes, 4), Ok((Op::JMPIF(Case::ZERO, 2), 4)));
+        assert_eq!(decode(&bytes, 8), Ok((Op::RETI, 1)));
+        assert_eq!(decode(&bytes, 9), Err(DecodeError::UnknownOpcode { pc: 9, opcode: 0x42 }));
+        assert_eq!(decode(&bytes, 10), Err(DecodeError::UnknownCondition { pc: 10, code: 0x09 }));
+        assert_eq!(decode(&bytes, 14), Err(DecodeError::Truncated { pc: 14 }));
+        assert_eq!(decode(&bytes, 17), Err(DecodeError::Truncated { pc: 17 }));
+        assert_eq!(decode(&bytes, usize::MAX), Err(DecodeError::Truncated { pc: usize::MAX }));
+        assert_eq!(decode(&bytes[..2], 0), Err(DecodeError::Truncated { pc: 0 }));
+
+        // every opcode and condition code cut short anywhere, and random garbage
+        for opcode in 0..=u8::MAX {
+            for code in 0..=u8::MAX {
+                let bytes = [opcode, code, 1, 2, 3];
+                for len in 0..=bytes.len() {
+                    if let Ok((op, size)) = decode(&bytes[..len], 0) {
+                        assert_eq!(size, op.get_size());
+                        assert!(size <= len);
+                    }
+                }
+            }
+        }
+        let garbage: Vec<u8> = XorShift(7).take(4096).collect();
+        for pc in 0..garbage.len() + 2 {
+            match decode(&garbage, pc) {
+                Ok((op, size)) => assert_eq!(&garbage[pc..pc + size], &{
+                    let mut bytes = Vec::new();
+                    op.encode(&mut bytes);
+                    bytes
+                }[..]),
+                Err(e) => assert_eq!(e.pc(), pc),
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use crate::CAddress;
+
+/// Labels of a compiled program, sorted by address
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Symbols {
+    labels: Vec<(CAddress, String)>,
+}
+
+impl Symbols {
+    pub fn new(labels: &HashMap<String, CAddress>) -> Self {
+        let mut labels: Vec<(CAddress, String)> = labels
+            .iter()
+            .map(|(name, addr)| (*addr, name.to_owned()))
+            .collect();
+        labels.sort();
+        Self { labels }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (CAddress, &str)> {
+        self.labels.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<CAddress> {
+        self.iter().find(|(_, n)| *n == name).map(|(addr, _)| addr)
+    }
+
+    /// First label defined exactly at `addr`
+    pub fn label_at(&self, addr: CAddress) -> Option<&str> {
+        self.iter().find(|(a, _)| *a == addr).map(|(_, name)| name)
+    }
+
+    /// Closest label at or before `addr`
+    pub fn nearest(&self, addr: CAddress) -> Option<(CAddress, &str)> {
+        self.iter().take_while(|(a, _)| *a <= addr).last()
+    }
+
+    /// One `<address> <name>` pair per line, address in hexadecimal
+    pub fn to_text(&self) -> String {
+        self.iter()
+            .map(|(addr, name)| format!("{:#06x} {}\n", addr, name))
+            .collect()
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut labels = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (addr, name) = line
+                .trim()
+                .split_once(' ')
+                .ok_or(format!("line {}: expected an address and a name", n + 1))?;
+            let addr = CAddress::from_str_radix(addr.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("line {}: invalid address '{}'", n + 1, addr))?;
+            labels.insert(name.trim().to_owned(), addr);
+        }
+        Ok(Self::new(&labels))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_symbols_text() {
+        let labels = HashMap::from([("swap".to_owned(), 0x3f), ("pass".to_owned(), 0x27)]);
+        let symbols = Symbols::new(&labels);
+        assert_eq!(symbols.to_text(), "0x0027 pass\n0x003f swap\n");
+        assert_eq!(Symbols::from_text(&symbols.to_text()), Ok(symbols.clone()));
+
+        assert_eq!(symbols.nearest(0x30), Some((0x27, "pass")));
+        assert_eq!(symbols.nearest(0x10), None);
+        assert_eq!(symbols.address_of("swap"), Some(0x3f));
+    }
+}